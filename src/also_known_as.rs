@@ -0,0 +1,127 @@
+//! alsoKnownAs
+//!
+//! The owner of a DID links it to the other identifiers of its subject, such as "did:web:example.com", in the
+//! alsoKnownAs of the document. A link to another DID of this contract is verified when that DID links back,
+//! the verified links are the verifiedAlsoKnownAs of the DID in the document metadata of `resolve`.
+//!
+//! The contract can only read the documents of its own DIDs, so the links to other identifiers, including the DIDs
+//! of another method or of another contract, are never verified. A resolver has to check their back-links itself.
+
+use super::*;
+
+#[derive(Serialize, Deserialize)]
+pub struct AlsoKnownAs {
+    pub id: String,
+    /// true if `id` is a DID of this contract whose alsoKnownAs has the DID.
+    pub verified: bool,
+}
+
+#[near_bindgen]
+impl DID {
+    /// add_also_known_as
+    ///
+    /// this method will add `uri` to the alsoKnownAs of the DID, `uri` is an absolute URI such as "did:web:example.com".
+    /// only the DID owner has the right to invoke this method.
+    ///
+    /// a link to a DID of this contract is verified once that DID adds the DID to its alsoKnownAs too,
+    /// a link to any other identifier is stored but never verified.
+    ///
+    /// log information: "add_also_known_as, did: did:near:abcde.testnet, uri: did:web:example.com"
+    ///
+    pub fn add_also_known_as(&mut self, uri: String) {
+        self.check_not_paused("add_also_known_as", PauseGroup::AlsoKnownAs);
+        let initial_storage = env::storage_usage();
+        let did = self.caller_did();
+
+        let mut record = self.get_valid_record(&did);
+        self.check_access(&did, &record);
+        self.check_also_known_as_entry("add_also_known_as", &did, &uri);
+        if record.also_known_as.contains(&uri) {
+            env::panic(b"add_also_known_as, uri exists")
+        }
+        assert_valid(
+            "add_also_known_as",
+            check_count(
+                "alsoKnownAs",
+                record.also_known_as.len() + 1,
+                self.config.limits.max_also_known_as,
+            ),
+        );
+
+        let log_message = format!("add_also_known_as, did: {}, uri: {}", &did, &uri);
+        record.also_known_as.push(uri);
+        record.updated = env::block_timestamp();
+        self.save_record(&did, record, "add_also_known_as", None);
+        self.charge_storage(&did, initial_storage);
+        env::log(log_message.as_bytes());
+    }
+
+    /// remove_also_known_as
+    ///
+    /// this method will remove `uri` from the alsoKnownAs of the DID. only the DID owner has the right to invoke this method.
+    ///
+    /// log information: "remove_also_known_as, did: did:near:abcde.testnet, uri: did:web:example.com"
+    ///
+    pub fn remove_also_known_as(&mut self, uri: String) {
+        self.check_not_paused("remove_also_known_as", PauseGroup::AlsoKnownAs);
+        let initial_storage = env::storage_usage();
+        let did = self.caller_did();
+
+        let mut record = self.get_valid_record(&did);
+        self.check_access(&did, &record);
+        let index = match record.also_known_as.iter().position(|x| x == &uri) {
+            Some(v) => v,
+            None => env::panic(b"remove_also_known_as, uri doesn't exist"),
+        };
+
+        record.also_known_as.remove(index);
+        record.updated = env::block_timestamp();
+        self.save_record(&did, record, "remove_also_known_as", None);
+        self.charge_storage(&did, initial_storage);
+
+        let log_message = format!("remove_also_known_as, did: {}, uri: {}", &did, &uri);
+        env::log(log_message.as_bytes());
+    }
+
+    /// get_also_known_as
+    ///
+    /// this method query the alsoKnownAs of the DID and whether each link is verified, in the order they were added.
+    /// only the links to the DIDs of this contract can be verified, `verified` is false for the others.
+    ///
+    pub fn get_also_known_as(&self, did: String) -> Vec<AlsoKnownAs> {
+        let record = match self.record.get(&did) {
+            Some(v) if v.is_valid() => v,
+            _ => return vec![],
+        };
+        record
+            .also_known_as
+            .into_iter()
+            .map(|id| AlsoKnownAs {
+                verified: self.is_link_verified(&did, &id),
+                id,
+            })
+            .collect()
+    }
+}
+
+impl DID {
+    /// panic if `uri` can't be an alsoKnownAs entry of the DID.
+    pub(crate) fn check_also_known_as_entry(&self, method: &str, did: &String, uri: &String) {
+        assert_valid(
+            method,
+            check_length("alsoKnownAs", uri.len(), self.config.limits.max_did_length),
+        );
+        assert_valid(method, check_also_known_as(uri));
+        if uri == did {
+            env::panic(format!("{}, alsoKnownAs can't be the DID itself", method).as_bytes())
+        }
+    }
+
+    /// return true if `uri` is a valid DID of this contract which links back to the DID.
+    pub(crate) fn is_link_verified(&self, did: &String, uri: &String) -> bool {
+        match self.record.get(uri) {
+            Some(record) => record.is_valid() && record.also_known_as.contains(did),
+            None => false,
+        }
+    }
+}
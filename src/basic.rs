@@ -1,12 +1,141 @@
 use super::*;
 use base58::*;
+use serde::ser::SerializeMap;
+use serde::{Deserializer, Serializer};
 
-#[derive(BorshDeserialize, BorshSerialize)]
+#[derive(BorshDeserialize, BorshSerialize, Clone, PartialEq)]
 pub enum Status {
     VALID = 0x00,
     DEACTIVATED = 0x01,
 }
 
+/// how the owner of a DID is authorized.
+#[derive(
+    BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, PartialEq, Debug,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthMode {
+    /// by an authentication key of the DID signing the transaction, the DID is the one of the signer account.
+    Signer,
+    /// by the predecessor account, so that a contract can own the DID and call through cross-contract calls.
+    Predecessor,
+}
+
+/// the contract-level configuration.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+pub struct Config {
+    /// the schemes allowed in service endpoint URIs.
+    pub endpoint_schemes: Vec<String>,
+    /// the registered service types, any type is allowed if it's empty.
+    pub service_types: Vec<String>,
+    /// the per-DID quotas and field length limits.
+    pub limits: Limits,
+    /// the allowed JSON-LD contexts, any context is allowed if it's empty.
+    #[serde(default)]
+    pub context_allowlist: Vec<String>,
+    /// the prefix of the DIDs, including the method, such as "did:near:".
+    #[serde(default = "default_method_prefix")]
+    pub method_prefix: String,
+    /// the network segment following the method prefix, such as "testnet" for "did:near:testnet:alice.testnet".
+    /// the DIDs have no network segment if it's None.
+    #[serde(default)]
+    pub network: Option<String>,
+}
+
+fn default_method_prefix() -> String {
+    DEFAULT_METHOD_PREFIX.to_string()
+}
+
+impl Config {
+    /// the prefix of the DIDs of this contract, the method prefix followed by the network segment.
+    pub fn did_prefix(&self) -> String {
+        match &self.network {
+            Some(network) => format!("{}{}:", self.method_prefix, network),
+            None => self.method_prefix.clone(),
+        }
+    }
+
+    pub fn gen_did(&self, account_id: &str) -> String {
+        self.did_prefix() + account_id
+    }
+
+    /// panic if the DID is not a DID of this contract.
+    pub fn check_did(&self, did: &str) {
+        let prefix = self.did_prefix();
+        if !did.starts_with(&prefix) || did.len() == prefix.len() {
+            env::panic(b"check_did, invalid DID")
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            endpoint_schemes: vec![
+                "https".to_string(),
+                "http".to_string(),
+                "wss".to_string(),
+                "ws".to_string(),
+                "did".to_string(),
+            ],
+            service_types: vec![],
+            limits: Limits::default(),
+            context_allowlist: vec![],
+            method_prefix: default_method_prefix(),
+            network: None,
+        }
+    }
+}
+
+/// the per-DID quotas and field length limits, they keep the document small enough to be queried.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Limits {
+    /// the max number of public keys of a DID, including the deactivated ones.
+    pub max_public_keys: u32,
+    /// the max number of services of a DID.
+    pub max_services: u32,
+    /// the max number of contexts of a DID, the default contexts are not counted.
+    pub max_contexts: u32,
+    /// the max number of controllers of a DID.
+    pub max_controllers: u32,
+    /// the max length of a public key, in bytes.
+    pub max_public_key_length: u32,
+    /// the max length of a DID used as controller, or of an alsoKnownAs entry, in bytes.
+    pub max_did_length: u32,
+    /// the max length of a context URI, in bytes.
+    pub max_context_length: u32,
+    /// the max length of a service serialized as JSON, in bytes.
+    pub max_service_length: u32,
+    /// the max number of alsoKnownAs entries of a DID.
+    #[serde(default = "default_max_also_known_as")]
+    pub max_also_known_as: u32,
+}
+
+fn default_max_also_known_as() -> u32 {
+    16
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_public_keys: 32,
+            max_services: 32,
+            max_contexts: 16,
+            max_controllers: 16,
+            max_public_key_length: 66,
+            max_did_length: 128,
+            max_context_length: 256,
+            max_service_length: 4096,
+            max_also_known_as: default_max_also_known_as(),
+        }
+    }
+}
+
+pub const DEFAULT_METHOD_PREFIX: &'static str = "did:near:";
+
+pub const RELATIONSHIP_PUBLIC_KEY: &'static str = "publicKey";
+pub const RELATIONSHIP_AUTHENTICATION: &'static str = "authentication";
+
 #[derive(Debug)]
 pub enum KeyType {
     Ed25519VerificationKey2018,
@@ -24,13 +153,37 @@ impl KeyType {
     }
 }
 
-pub fn gen_did(account_id: &str) -> String {
-    String::from("did:near:") + account_id
+/// the id of the key at `index` of the DID, index starts from 0.
+pub fn key_id(did: &str, index: u32) -> String {
+    format!("{}#keys-{}", did, index + 1)
+}
+
+/// the index of the key from its key id "did#keys-n", None if it's not a key id of the DID.
+pub fn parse_key_id(did: &str, key_id: &str) -> Option<u32> {
+    let n: u32 = key_id
+        .strip_prefix(did)?
+        .strip_prefix("#keys-")?
+        .parse()
+        .ok()?;
+    n.checked_sub(1)
+}
+
+/// the storage key of the service written in the version `version_id` of the DID.
+pub fn service_key(did: &str, service_id: &str, version_id: u64) -> String {
+    format!("{}#{}?versionId={}", did, service_id, version_id)
+}
+
+pub fn version_key(did: &str, version_id: u64) -> String {
+    format!("{}?versionId={}", did, version_id)
+}
+
+pub fn operation_key(did: &str, index: u64) -> String {
+    format!("{}?operation={}", did, index)
 }
 
-pub fn check_did(did: &str) {
-    let head = &did[0..9];
-    assert_eq!(head, "did:near:")
+/// the storage key of the index of the public key in the DID.
+pub fn key_lookup_key(did: &str, pk: &[u8]) -> String {
+    format!("{}?publicKey={}", did, pk.to_base58())
 }
 
 #[derive(BorshDeserialize, BorshSerialize)]
@@ -72,152 +225,138 @@ impl PublicKey {
             is_authentication: true,
         }
     }
-}
 
-#[derive(BorshDeserialize, BorshSerialize)]
-pub struct PublicKeyList {
-    public_key_list: Vec<PublicKey>,
-}
+    pub fn public_key(&self) -> &Vec<u8> {
+        &self.public_key
+    }
 
-impl PublicKeyList {
-    pub fn new_default(controller: &str, pk: Vec<u8>) -> Self {
-        PublicKeyList {
-            public_key_list: vec![PublicKey::new_pk_and_auth(controller, pk)],
-        }
+    pub fn controller(&self) -> &String {
+        &self.controller
     }
 
-    pub fn push(&mut self, pk: PublicKey) {
-        self.public_key_list.push(pk);
+    pub fn is_deactivated(&self) -> bool {
+        self.deactivated
     }
 
-    pub fn len(&self) -> u32 {
-        self.public_key_list.len() as u32
+    pub fn is_pk_list(&self) -> bool {
+        self.is_pk_list
     }
 
-    pub fn pk_exist(&self, pk: &Vec<u8>) -> bool {
-        for v in self.public_key_list.iter() {
-            if &v.public_key == pk {
-                return true;
-            }
-        }
-        return false;
+    pub fn is_authentication(&self) -> bool {
+        self.is_authentication
     }
 
-    pub fn deactivate_pk(&mut self, pk: &Vec<u8>) {
-        for v in self.public_key_list.iter_mut() {
-            if &v.public_key == pk {
-                if v.deactivated {
-                    env::panic(b"deactivate_pk, pk is deactivated")
-                }
-                v.deactivated = true;
-                return;
-            }
+    pub fn deactivate(&mut self) {
+        if self.deactivated {
+            env::panic(b"deactivate_pk, pk is deactivated")
         }
-        env::panic(b"deactivate_pk, pk doesn't exist")
+        self.deactivated = true;
     }
 
-    pub fn check_pk_access(&self, pk: &Vec<u8>) {
-        for v in self.public_key_list.iter() {
-            if &v.public_key == pk {
-                if v.deactivated {
-                    env::panic(b"check_pk_access, pk is deactivated")
-                }
-                if !v.is_authentication {
-                    env::panic(b"check_pk_access, pk is not authentication")
-                }
-                return;
-            }
+    pub fn set_auth(&mut self) {
+        if self.deactivated {
+            env::panic(b"set_pk_auth, pk is deactivated")
         }
-        env::panic(b"check_pk_access, pk doesn't exist")
+        if self.is_authentication {
+            env::panic(b"set_pk_auth, pk is already auth key")
+        }
+        self.is_authentication = true;
     }
 
-    pub fn set_pk_auth(&mut self, pk: &Vec<u8>) -> usize {
-        for (index, v) in self.public_key_list.iter_mut().enumerate() {
-            if &v.public_key == pk {
-                if v.deactivated {
-                    env::panic(b"set_pk_auth, pk is deactivated")
-                }
-                if v.is_authentication {
-                    env::panic(b"set_pk_auth, pk is already auth key")
-                }
-                v.is_authentication = true;
-                return index;
-            }
+    pub fn remove_auth(&mut self) {
+        if self.deactivated {
+            env::panic(b"remove_pk_auth, pk is deactivated")
+        }
+        if !self.is_authentication {
+            env::panic(b"remove_pk_auth, pk is not auth key")
         }
-        env::panic(b"set_pk_auth, pk doesn't exist")
+        self.is_authentication = false;
     }
 
-    pub fn remove_pk_auth(&mut self, pk: &Vec<u8>) -> usize {
-        for (index, v) in self.public_key_list.iter_mut().enumerate() {
-            if &v.public_key == pk {
-                if v.deactivated {
-                    env::panic(b"remove_pk_auth, pk is deactivated")
-                }
-                if !v.is_authentication {
-                    env::panic(b"remove_pk_auth, pk is not auth key")
-                }
-                v.is_authentication = false;
-                return index;
-            }
+    /// `index` is the position of the key in the DID, starting from 0.
+    pub fn to_json(&self, did: &str, index: u32) -> PublicKeyJson {
+        let mut tp: String = "".to_string();
+        match self.public_key[0] {
+            0 => tp = KeyType::Ed25519VerificationKey2018.to_string(),
+            1 => tp = KeyType::EcdsaSecp256k1VerificationKey2019.to_string(),
+            _ => {}
+        }
+        PublicKeyJson {
+            id: key_id(did, index),
+            tp,
+            controller: self.controller.clone(),
+            public_key_base58: self.public_key.to_base58(),
         }
-        env::panic(b"remove_pk_auth, pk doesn't exist")
     }
 
-    pub fn get_pk_json(&self, did: &str) -> Vec<PublicKeyJson> {
-        let mut result = vec![];
-        for (i, v) in self.public_key_list.iter().enumerate() {
-            if !v.is_pk_list {
-                continue;
-            }
-            let mut tp: String = "".to_string();
-            match v.public_key[0] {
-                0 => tp = KeyType::Ed25519VerificationKey2018.to_string(),
-                1 => tp = KeyType::EcdsaSecp256k1VerificationKey2019.to_string(),
-                _ => {}
-            }
-            let public_key_json = PublicKeyJson {
-                id: format!("{}#keys-{}", did, i + 1),
-                tp,
-                controller: v.controller.clone(),
-                public_key_base58: v.public_key.to_base58(),
-            };
-            result.push(public_key_json);
+    /// return true if the key is valid and used for the relationship, "publicKey" or "authentication".
+    pub fn has_relationship(&self, relationship: &str) -> bool {
+        if self.deactivated {
+            return false;
+        }
+        match relationship {
+            RELATIONSHIP_PUBLIC_KEY => self.is_pk_list,
+            RELATIONSHIP_AUTHENTICATION => self.is_authentication,
+            _ => env::panic(b"has_relationship, unknown relationship"),
         }
-        result
     }
+}
 
-    pub fn get_authentication_json(
-        &self,
-        did: &str,
-        authentication_list: Vec<u32>,
-    ) -> Vec<Authentication> {
-        let mut result = vec![];
-        for i in authentication_list.iter() {
-            let public_key: &PublicKey = self.public_key_list.get(*i as usize).unwrap();
-            if public_key.is_pk_list {
-                let authentication = Authentication::Pk(format!("{}#keys-{}", did, i + 1));
-                result.push(authentication);
-            } else {
-                let mut tp: String = "".to_string();
-                match public_key.public_key[0] {
-                    0 => tp = KeyType::Ed25519VerificationKey2018.to_string(),
-                    1 => tp = KeyType::EcdsaSecp256k1VerificationKey2019.to_string(),
-                    _ => {}
-                }
-                let authentication = Authentication::NotPK(PublicKeyJson {
-                    id: format!("{}#keys-{}", did, i + 1),
-                    tp,
-                    controller: public_key.controller.clone(),
-                    public_key_base58: public_key.public_key.to_base58(),
-                });
-                result.push(authentication);
-            }
+/// the state of a DID, it's small enough to be read and written by every update.
+///
+/// public keys and services are stored separately, the record only references them,
+/// a copy of the record is kept for every version of the DID.
+#[derive(BorshDeserialize, BorshSerialize, Clone)]
+pub struct DidRecord {
+    pub status: Status,
+    pub contexts: Vec<String>,
+    /// the number of public keys, the keys are stored with their key id "did#keys-n".
+    pub key_count: u32,
+    /// the index of the authentication keys, in the order they were set.
+    pub authentication: Vec<u32>,
+    pub controllers: Vec<String>,
+    /// the id of the services and the version in which they were written.
+    pub services: Vec<(String, u64)>,
+    pub created: u64,
+    pub updated: u64,
+    /// the versionId of the DID, it's increased by every update.
+    pub version: u64,
+    pub auth_mode: AuthMode,
+    /// the account which the key-derived DID is bound to.
+    pub bound_account: Option<String>,
+    /// the other identifiers of the DID subject, such as "did:web:example.com".
+    pub also_known_as: Vec<String>,
+}
+
+impl DidRecord {
+    pub fn new(created: u64) -> Self {
+        DidRecord {
+            status: Status::VALID,
+            contexts: vec![],
+            key_count: 0,
+            authentication: vec![],
+            controllers: vec![],
+            services: vec![],
+            created,
+            updated: 0,
+            version: 0,
+            auth_mode: AuthMode::Signer,
+            bound_account: None,
+            also_known_as: vec![],
         }
-        result
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.status == Status::VALID
+    }
+
+    pub fn service_index(&self, service_id: &str) -> Option<usize> {
+        self.services.iter().position(|(id, _)| id == service_id)
     }
 }
 
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct PublicKeyJson {
     id: String,
     #[serde(rename(serialize = "type", deserialize = "type"))]
@@ -227,13 +366,38 @@ pub struct PublicKeyJson {
     public_key_base58: String,
 }
 
+impl PublicKeyJson {
+    /// the index of the key in the DID, its controller and the public key, the type must match the key.
+    pub fn parse(&self, did: &str) -> Result<(u32, String, Vec<u8>), String> {
+        let index = match parse_key_id(did, &self.id) {
+            Some(v) => v,
+            None => return Err(format!("invalid key id \"{}\"", self.id)),
+        };
+        let pk = match self.public_key_base58.from_base58() {
+            Ok(v) if !v.is_empty() => v,
+            _ => return Err(format!("invalid publicKeyBase58 of key \"{}\"", self.id)),
+        };
+        let tp = match pk[0] {
+            0 => KeyType::Ed25519VerificationKey2018.to_string(),
+            1 => KeyType::EcdsaSecp256k1VerificationKey2019.to_string(),
+            _ => "".to_string(),
+        };
+        if tp.is_empty() || tp != self.tp {
+            return Err(format!("invalid type of key \"{}\"", self.id));
+        }
+        Ok((index, self.controller.clone(), pk))
+    }
+}
+
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
 pub struct Service {
     pub id: String,
     #[serde(rename(serialize = "type", deserialize = "type"))]
-    pub tp: String,
+    pub tp: ServiceType,
     #[serde(rename(serialize = "serviceEndpoint", deserialize = "serviceEndpoint"))]
-    pub service_endpoint: String,
+    pub service_endpoint: ServiceEndpoint,
+    #[serde(flatten)]
+    pub properties: JsonMap,
 }
 
 #[cfg(test)]
@@ -241,12 +405,108 @@ impl Service {
     pub fn new(id: String, tp: String, service_endpoint: String) -> Self {
         Service {
             id,
-            tp,
-            service_endpoint,
+            tp: ServiceType::Type(tp),
+            service_endpoint: ServiceEndpoint::Uri(service_endpoint),
+            properties: JsonMap::default(),
+        }
+    }
+}
+
+/// the type of a service, a string or an array of strings.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(untagged)]
+pub enum ServiceType {
+    Type(String),
+    Types(Vec<String>),
+}
+
+/// the endpoint of a service, a URI, a map or an ordered set of them.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(untagged)]
+pub enum ServiceEndpoint {
+    Uri(String),
+    Map(JsonMap),
+    Set(Vec<ServiceEndpoint>),
+}
+
+/// a JSON object, the values are stored as JSON text so that they can be any JSON value.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Default, PartialEq, Debug)]
+pub struct JsonMap(Vec<(String, String)>);
+
+impl JsonMap {
+    pub fn get(&self, key: &str) -> Option<serde_json::Value> {
+        self.0
+            .iter()
+            .find(|(k, _)| k == key)
+            .and_then(|(_, v)| serde_json::from_str(v).ok())
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &String> {
+        self.0.iter().map(|(k, _)| k)
+    }
+
+    /// return true if the map uses a key reserved by the service itself, such as "id".
+    pub fn contains_reserved_key(&self) -> bool {
+        self.keys()
+            .any(|k| k == "id" || k == "type" || k == "serviceEndpoint")
+    }
+}
+
+impl Serialize for JsonMap {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (k, v) in self.0.iter() {
+            let value: serde_json::Value =
+                serde_json::from_str(v).map_err(serde::ser::Error::custom)?;
+            map.serialize_entry(k, &value)?;
         }
+        map.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for JsonMap {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let map = serde_json::Map::<String, serde_json::Value>::deserialize(deserializer)?;
+        Ok(JsonMap(
+            map.into_iter().map(|(k, v)| (k, v.to_string())).collect(),
+        ))
     }
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct DidPage {
+    pub dids: Vec<String>,
+    /// the index the next page starts from, None after the last DID.
+    #[serde(rename(serialize = "nextIndex", deserialize = "nextIndex"))]
+    pub next_index: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct KeyReference {
+    pub did: String,
+    #[serde(rename(serialize = "keyId", deserialize = "keyId"))]
+    pub key_id: String,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+pub struct Operation {
+    pub kind: String,
+    #[serde(rename(serialize = "signerAccount", deserialize = "signerAccount"))]
+    pub signer_account: String,
+    #[serde(rename(serialize = "signerKeyBase58", deserialize = "signerKeyBase58"))]
+    pub signer_key: String,
+    /// the account which invoked the contract, it's a contract for the DIDs owned by contracts.
+    #[serde(rename(serialize = "predecessorAccount", deserialize = "predecessorAccount"))]
+    pub predecessor_account: String,
+    /// the controller DID if the operation was done by a controller, None if it was done by the owner.
+    pub controller: Option<String>,
+    #[serde(rename(serialize = "blockHeight", deserialize = "blockHeight"))]
+    pub block_height: u64,
+    pub timestamp: u64,
+    #[serde(rename(serialize = "versionId", deserialize = "versionId"))]
+    pub version_id: u64,
+}
+
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum Authentication {
@@ -254,16 +514,29 @@ pub enum Authentication {
     NotPK(PublicKeyJson),
 }
 
+/// the DID document, it's also accepted as input with any of its fields missing.
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
 pub struct Document {
-    #[serde(rename(serialize = "@contexts", deserialize = "@contexts"))]
+    #[serde(default, rename(serialize = "@contexts", deserialize = "@contexts"))]
     pub contexts: Vec<String>,
+    #[serde(default)]
     pub id: String,
-    #[serde(rename(serialize = "publicKey", deserialize = "publicKey"))]
+    #[serde(default, rename(serialize = "publicKey", deserialize = "publicKey"))]
     pub public_key: Vec<PublicKeyJson>,
+    #[serde(default)]
     pub authentication: Vec<Authentication>,
+    #[serde(default)]
     pub controller: Vec<String>,
+    #[serde(
+        default,
+        rename(serialize = "alsoKnownAs", deserialize = "alsoKnownAs"),
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub also_known_as: Vec<String>,
+    #[serde(default)]
     pub service: Vec<Service>,
+    #[serde(default)]
     pub created: u64,
+    #[serde(default)]
     pub updated: u64,
 }
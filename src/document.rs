@@ -0,0 +1,383 @@
+//! document updates
+//!
+//! A DID is stored as a record referencing its keys and services, and rendered as a document by `get_document`.
+//! The methods here take the document itself: the target document is applied to the stores in one update,
+//! the keys, services, contexts, controllers and alsoKnownAs which differ from the current ones are written and the others are kept.
+//! `patch_document` gets the target document by applying a JSON Patch to the current one.
+
+use super::*;
+use std::collections::BTreeMap;
+
+/// a key of the target document, its controller, the public key and whether it's listed in "publicKey".
+struct DocumentKey {
+    controller: String,
+    pk: Vec<u8>,
+    is_pk_list: bool,
+}
+
+/// parse the key of the document and add it to `keys`, return its index.
+fn insert_document_key(
+    method: &str,
+    did: &String,
+    keys: &mut BTreeMap<u32, DocumentKey>,
+    key: &PublicKeyJson,
+    is_pk_list: bool,
+) -> u32 {
+    let (index, controller, pk) = match key.parse(did) {
+        Ok(v) => v,
+        Err(e) => env::panic(format!("{}, {}", method, e).as_bytes()),
+    };
+    if keys.contains_key(&index) {
+        env::panic(format!("{}, key \"{}\" is duplicated", method, key_id(did, index)).as_bytes())
+    }
+    keys.insert(
+        index,
+        DocumentKey {
+            controller,
+            pk,
+            is_pk_list,
+        },
+    );
+    index
+}
+
+/// the pause group of the member of the document at `path`, the computed members and the default contexts can't be changed.
+fn patch_group(path: &str) -> Result<PauseGroup, String> {
+    let tokens = parse_pointer(path)?;
+    match tokens.first().map(|x| x.as_str()) {
+        Some("@contexts") => match tokens.get(1).map(|x| x.as_str()) {
+            Some("0") | Some("1") => Err(format!("\"{}\" is a default context", path)),
+            _ => Ok(PauseGroup::Contexts),
+        },
+        Some("publicKey") | Some("authentication") => Ok(PauseGroup::Keys),
+        Some("controller") => Ok(PauseGroup::Controllers),
+        Some("service") => Ok(PauseGroup::Services),
+        Some("alsoKnownAs") => Ok(PauseGroup::AlsoKnownAs),
+        Some("id") | Some("created") | Some("updated") => Err(format!("\"{}\" is computed", path)),
+        Some(_) => Err(format!("\"{}\" is not a member of the document", path)),
+        None => Err("the document can't be replaced".to_string()),
+    }
+}
+
+#[near_bindgen]
+impl DID {
+    /// reg_did_with_document
+    ///
+    /// this method will register the DID of the signer account with the keys, authentication, services, contexts, controllers and alsoKnownAs of `document`.
+    /// the document has the shape returned by `get_document`, its fields can be missing, and the result is the document of the DID.
+    ///
+    /// the key ids are "did#keys-1" to "did#keys-n", the keys which are only used for authentication are embedded in "authentication".
+    /// the signer key must be an authentication key. "id" must be the DID if it's given, "created" and "updated" are ignored.
+    ///
+    /// the attached deposit is added to the storage balance of the DID, it must cover the storage used by the DID.
+    ///
+    /// log information: "reg_did_with_document: did:near:abcde.testnet"
+    ///
+    pub fn reg_did_with_document(&mut self, document: Document) -> String {
+        self.check_not_paused("reg_did_with_document", PauseGroup::Registration);
+        let initial_storage = env::storage_usage();
+        let account_id = env::signer_account_id();
+        let did = self.config.gen_did(&account_id);
+
+        if self.record.get(&did).is_some() {
+            env::panic(b"reg_did_with_document, did exists")
+        }
+        if self.bound_did.get(&account_id).is_some() {
+            env::panic(b"reg_did_with_document, account is bound")
+        }
+
+        let record = DidRecord::new(env::block_timestamp());
+        let record = self.apply_document("reg_did_with_document", &did, record, document);
+        match self.find_key(&did, &env::signer_account_pk()) {
+            Some((_, public_key)) if public_key.has_relationship(RELATIONSHIP_AUTHENTICATION) => (),
+            _ => env::panic(b"reg_did_with_document, signer pk is not authentication"),
+        }
+        self.dids.push(&did);
+        self.save_record(&did, record, "reg_did_with_document", None);
+        self.charge_storage(&did, initial_storage);
+
+        let log_message = format!("reg_did_with_document: {}", &did);
+        env::log(log_message.as_bytes());
+        self.get_document(did).unwrap()
+    }
+
+    /// patch_document
+    ///
+    /// this method will apply the JSON Patch `patch`, RFC 6902, to the document of the DID returned by `get_document`,
+    /// and update the keys, authentication, services, contexts, controllers and alsoKnownAs to the patched document.
+    /// the patch is applied as a whole or not at all, and it must change the document.
+    ///
+    /// "id", "created" and "updated" are computed, only "test" can refer to them.
+    /// the two default contexts must stay the first ones. the keys can't be changed, a key removed from the document is deactivated,
+    /// a deactivated key is still listed in "publicKey" but can't be an authentication key, and a new key gets the id following the last key,
+    /// including the deactivated ones. the pause group of every member changed by the patch is checked.
+    ///
+    /// log information: "patch_document, did: did:near:abcde.testnet, operations: 2"
+    ///
+    pub fn patch_document(&mut self, patch: Vec<JsonPatchOp>) {
+        let mut groups = vec![];
+        for op in patch.iter() {
+            for path in op.changed_paths() {
+                let group = match patch_group(path) {
+                    Ok(v) => v,
+                    Err(e) => env::panic(format!("patch_document, {}", e).as_bytes()),
+                };
+                if !groups.contains(&group) {
+                    groups.push(group);
+                }
+            }
+        }
+        for group in groups {
+            self.check_not_paused("patch_document", group);
+        }
+        let initial_storage = env::storage_usage();
+        let did = self.caller_did();
+
+        let record = self.get_valid_record(&did);
+        self.check_access(&did, &record);
+        if patch.is_empty() {
+            env::panic(b"patch_document, patch is empty")
+        }
+
+        let document = self.render_document(did.clone(), &record).unwrap();
+        let original: serde_json::Value = serde_json::from_str(&document).unwrap();
+        let mut document = original.clone();
+        for (i, op) in patch.iter().enumerate() {
+            assert_valid(
+                "patch_document",
+                apply_patch_op(&mut document, op).map_err(|e| format!("operation {}, {}", i, e)),
+            );
+        }
+        if document == original {
+            env::panic(b"patch_document, patch doesn't change the document")
+        }
+        let document: Document = match serde_json::from_value(document) {
+            Ok(v) => v,
+            Err(e) => env::panic(format!("patch_document, invalid document: {}", e).as_bytes()),
+        };
+        if document.contexts.len() < 2
+            || document.contexts[0] != DEFAULT_CONTEXT1
+            || document.contexts[1] != DEFAULT_CONTEXT2
+        {
+            env::panic(b"patch_document, the default contexts can't be changed")
+        }
+        let mut record = self.apply_document("patch_document", &did, record, document);
+        record.updated = env::block_timestamp();
+        self.save_record(&did, record, "patch_document", None);
+        self.charge_storage(&did, initial_storage);
+
+        let log_message = format!("patch_document, did: {}, operations: {}", &did, patch.len());
+        env::log(log_message.as_bytes());
+    }
+}
+
+impl DID {
+    /// update the stores so that the DID renders as `document`, return the record referencing them.
+    ///
+    /// the existing keys must be unchanged or removed, a removed key is deactivated, and the new keys follow the existing ones.
+    /// a key which isn't in the rendered document, neither listed nor used for authentication, is kept as it is.
+    /// a deactivated key is rendered in "publicKey" like the other keys, it's kept as it is if it's unchanged.
+    /// the services which are unchanged keep the version they were written in.
+    pub(crate) fn apply_document(
+        &mut self,
+        method: &str,
+        did: &String,
+        mut record: DidRecord,
+        document: Document,
+    ) -> DidRecord {
+        if !document.id.is_empty() && &document.id != did {
+            env::panic(format!("{}, document id is not the DID", method).as_bytes())
+        }
+        let limits = self.config.limits.clone();
+
+        // the keys of the document by index, and the authentication keys in order
+        let mut keys: BTreeMap<u32, DocumentKey> = BTreeMap::new();
+        for v in document.public_key.iter() {
+            insert_document_key(method, did, &mut keys, v, true);
+        }
+        let mut authentication = vec![];
+        for v in document.authentication.iter() {
+            let index = match v {
+                Authentication::Pk(id) => match parse_key_id(did, id) {
+                    Some(i) if keys.get(&i).map(|x| x.is_pk_list).unwrap_or(false) => i,
+                    _ => env::panic(
+                        format!("{}, authentication \"{}\" doesn't exist", method, id).as_bytes(),
+                    ),
+                },
+                Authentication::NotPK(v) => insert_document_key(method, did, &mut keys, v, false),
+            };
+            if authentication.contains(&index) {
+                env::panic(
+                    format!(
+                        "{}, authentication \"{}\" is duplicated",
+                        method,
+                        key_id(did, index)
+                    )
+                    .as_bytes(),
+                )
+            }
+            authentication.push(index);
+        }
+        if !authentication.is_empty() {
+            self.check_auth_key_allowed(method, &record);
+        }
+
+        for i in 0..record.key_count {
+            let mut public_key = self.public_key.get(&key_id(did, i)).unwrap();
+            let is_authentication = authentication.contains(&i);
+            match keys.remove(&i) {
+                Some(key) => {
+                    if &key.pk != public_key.public_key()
+                        || &key.controller != public_key.controller()
+                        || key.is_pk_list != public_key.is_pk_list()
+                    {
+                        env::panic(
+                            format!("{}, key \"{}\" can't be changed", method, key_id(did, i))
+                                .as_bytes(),
+                        )
+                    }
+                    // a deactivated key is still listed in "publicKey", it can't be an authentication key again
+                    if public_key.is_deactivated() {
+                        if is_authentication {
+                            env::panic(
+                                format!("{}, key \"{}\" is deactivated", method, key_id(did, i))
+                                    .as_bytes(),
+                            )
+                        }
+                        continue;
+                    }
+                    if is_authentication && !public_key.is_authentication() {
+                        public_key.set_auth();
+                    } else if !is_authentication && public_key.is_authentication() {
+                        public_key.remove_auth();
+                    } else {
+                        continue;
+                    }
+                }
+                // a key which is neither listed nor used for authentication isn't in the document, it's kept
+                None if public_key.is_deactivated()
+                    || !(public_key.is_pk_list() || public_key.is_authentication()) =>
+                {
+                    continue
+                }
+                None => public_key.deactivate(),
+            }
+            self.public_key.insert(&key_id(did, i), &public_key);
+        }
+        for (i, key) in keys {
+            if i != record.key_count {
+                env::panic(
+                    format!(
+                        "{}, key \"{}\" doesn't follow the existing keys",
+                        method,
+                        key_id(did, i)
+                    )
+                    .as_bytes(),
+                )
+            }
+            self.config.check_did(&key.controller);
+            if self.find_key(did, &key.pk).is_some() {
+                env::panic(format!("{}, pk exists", method).as_bytes())
+            }
+            self.check_key_limits(method, &record, &key.pk, &key.controller);
+            let public_key = match (key.is_pk_list, authentication.contains(&i)) {
+                (true, true) => PublicKey::new_pk_and_auth(&key.controller, key.pk),
+                (true, false) => PublicKey::new_pk(&key.controller, key.pk),
+                _ => PublicKey::new_auth(&key.controller, key.pk),
+            };
+            self.push_key(did, &mut record, public_key);
+        }
+        record.authentication = authentication;
+
+        for (i, controller) in document.controller.iter().enumerate() {
+            if document.controller[..i].contains(controller) {
+                env::panic(format!("{}, controller exists", method).as_bytes())
+            }
+            self.config.check_did(controller);
+            assert_valid(
+                method,
+                check_length("controller", controller.len(), limits.max_did_length),
+            );
+        }
+        assert_valid(
+            method,
+            check_count(
+                "controllers",
+                document.controller.len(),
+                limits.max_controllers,
+            ),
+        );
+        for controller in record.controllers.iter() {
+            if !document.controller.contains(controller) {
+                self.unindex_controller(controller, did);
+            }
+        }
+        for controller in document.controller.iter() {
+            self.index_controller(controller, did);
+        }
+        record.controllers = document.controller;
+
+        record.contexts = self.check_contexts(method, vec![], document.contexts);
+
+        for (i, uri) in document.also_known_as.iter().enumerate() {
+            if document.also_known_as[..i].contains(uri) {
+                env::panic(format!("{}, uri exists", method).as_bytes())
+            }
+            self.check_also_known_as_entry(method, did, uri);
+        }
+        assert_valid(
+            method,
+            check_count(
+                "alsoKnownAs",
+                document.also_known_as.len(),
+                limits.max_also_known_as,
+            ),
+        );
+        record.also_known_as = document.also_known_as;
+
+        // a service which is written by this update gets the next version
+        let version = record.version + 1;
+        let mut services: Vec<(String, u64)> = vec![];
+        for mut ser in document.service {
+            if let Some(id) = ser.id.strip_prefix(&format!("{}#", did)) {
+                ser.id = id.to_string();
+            }
+            assert_valid(method, check_service(&ser, &self.config));
+            assert_valid(
+                method,
+                check_length(
+                    "service",
+                    service_json_length(&ser),
+                    limits.max_service_length,
+                ),
+            );
+            if services.iter().any(|(x, _)| x == &ser.id) {
+                env::panic(format!("{}, service exists", method).as_bytes())
+            }
+            let unchanged = record.service_index(&ser.id).and_then(|i| {
+                let written = record.services[i].1;
+                let current = self.service.get(&service_key(did, &ser.id, written))?;
+                if serde_json::to_string(&current).ok() == serde_json::to_string(&ser).ok() {
+                    Some(written)
+                } else {
+                    None
+                }
+            });
+            let written = match unchanged {
+                Some(v) => v,
+                None => {
+                    self.service
+                        .insert(&service_key(did, &ser.id, version), &ser);
+                    version
+                }
+            };
+            services.push((ser.id, written));
+        }
+        assert_valid(
+            method,
+            check_count("services", services.len(), limits.max_services),
+        );
+        record.services = services;
+        record
+    }
+}
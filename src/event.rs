@@ -0,0 +1,29 @@
+//! events
+//!
+//! Governance changes are logged as events in the format of NEP-297, "EVENT_JSON:" followed by the event JSON,
+//! so that indexers can follow them without parsing the log messages of every method.
+
+use super::*;
+
+pub const EVENT_STANDARD: &'static str = "did-near";
+pub const EVENT_VERSION: &'static str = "1.0.0";
+
+#[derive(Serialize)]
+struct Event<'a> {
+    standard: &'a str,
+    version: &'a str,
+    event: &'a str,
+    data: Vec<serde_json::Value>,
+}
+
+/// log the event with its data.
+pub fn emit_event(event: &str, data: serde_json::Value) {
+    let event = Event {
+        standard: EVENT_STANDARD,
+        version: EVENT_VERSION,
+        event,
+        data: vec![data],
+    };
+    let log_message = format!("EVENT_JSON:{}", serde_json::to_string(&event).unwrap());
+    env::log(log_message.as_bytes());
+}
@@ -0,0 +1,183 @@
+//! account factory
+//!
+//! The contract creates a subaccount of its own account, such as "user123.<contract account>", and registers
+//! the DID of the subaccount in the same call, with the full access key of the subaccount as its authentication key.
+//! The DID is registered before the account is created, and the callback removes it if the account isn't created.
+
+use super::*;
+use near_sdk::json_types::U64;
+use near_sdk::PromiseResult;
+
+/// the gas attached to the `on_account_created` callback.
+pub const ACCOUNT_CALLBACK_GAS: u64 = 20_000_000_000_000;
+/// the maximum length of a NEAR account id.
+const MAX_ACCOUNT_ID_LENGTH: usize = 64;
+
+/// return true if `pk` is a NEAR Ed25519 or Secp256k1 public key, which can be added to an account.
+fn is_account_key(pk: &[u8]) -> bool {
+    match pk.first() {
+        Some(0) => pk.len() == 33,
+        Some(1) => pk.len() == 65,
+        _ => false,
+    }
+}
+
+#[near_bindgen]
+impl DID {
+    /// create_account_and_did
+    ///
+    /// this method will create the subaccount "<name>.<contract account>" with `pk` as its full access key,
+    /// and register the DID of the subaccount with `pk` as its first public key and authentication key.
+    ///
+    /// the attached deposit pays for the storage of the DID first, the rest is the initial balance of the subaccount.
+    /// if the subaccount can't be created, for example because it exists, the DID is removed and the deposit is refunded to the caller.
+    ///
+    /// log information: "create_account_and_did: did:near:user123.abcde.testnet"
+    ///
+    pub fn create_account_and_did(&mut self, name: String, pk: Vec<u8>) {
+        self.check_not_paused("create_account_and_did", PauseGroup::Registration);
+        let initial_storage = env::storage_usage();
+        assert_valid("create_account_and_did", check_account_name(&name));
+        let account_id = format!("{}.{}", name, env::current_account_id());
+        if account_id.len() > MAX_ACCOUNT_ID_LENGTH {
+            env::panic(b"create_account_and_did, account id is too long")
+        }
+        if !is_account_key(&pk) {
+            env::panic(b"create_account_and_did, invalid public key")
+        }
+        let did = self.config.gen_did(&account_id);
+        if self.record.get(&did).is_some() {
+            env::panic(b"create_account_and_did, did exists")
+        }
+
+        let mut record = DidRecord::new(env::block_timestamp());
+        let index = self.push_key(
+            &did,
+            &mut record,
+            PublicKey::new_pk_and_auth(&did, pk.clone()),
+        );
+        record.authentication.push(index);
+        let did_index = self.dids.len();
+        self.dids.push(&did);
+        self.save_record(&did, record, "create_account_and_did", None);
+        self.charge_storage(&did, initial_storage);
+
+        // the deposit left after the storage of the DID funds the subaccount
+        let mut storage_account = self.storage_account.get(&did).unwrap();
+        let initial_balance = storage_account.available();
+        if initial_balance == 0 {
+            env::panic(b"create_account_and_did, deposit doesn't cover the initial balance")
+        }
+        storage_account.total -= initial_balance;
+        self.storage_account.insert(&did, &storage_account);
+
+        let log_message = format!("create_account_and_did: {}", &did);
+        env::log(log_message.as_bytes());
+
+        let args = serde_json::json!({
+            "did": did,
+            "account_id": account_id,
+            "payer": env::predecessor_account_id(),
+            "index": U64::from(did_index),
+            "deposit": U128::from(env::attached_deposit()),
+            "initial_balance": U128::from(initial_balance),
+        });
+        Promise::new(account_id)
+            .create_account()
+            .transfer(initial_balance)
+            .add_full_access_key(pk)
+            .then(Promise::new(env::current_account_id()).function_call(
+                b"on_account_created".to_vec(),
+                args.to_string().into_bytes(),
+                0,
+                ACCOUNT_CALLBACK_GAS,
+            ));
+    }
+
+    /// on_account_created
+    ///
+    /// this method is the callback of `create_account_and_did`, only the contract account has the right to invoke this method.
+    ///
+    /// if the subaccount wasn't created, the DID is removed with all its versions and operations, and its slot `index` in the
+    /// registered DIDs is left as a tombstone. `deposit`, the deposit attached to `create_account_and_did`, is refunded to the payer:
+    /// `initial_balance` came back to the contract and the rest was the storage balance of the DID. the deposits made by others
+    /// for the DID are kept in its storage balance, the account can withdraw them once it exists.
+    ///
+    /// log information: "on_account_created, account: user123.abcde.testnet, created: false"
+    ///
+    pub fn on_account_created(
+        &mut self,
+        did: String,
+        account_id: String,
+        payer: String,
+        index: U64,
+        deposit: U128,
+        initial_balance: U128,
+    ) -> bool {
+        if env::predecessor_account_id() != env::current_account_id() {
+            env::panic(b"on_account_created, only the contract account can invoke this method")
+        }
+        if env::promise_results_count() != 1 {
+            env::panic(b"on_account_created, expected one promise result")
+        }
+        let created = match env::promise_result(0) {
+            PromiseResult::Successful(_) => true,
+            _ => false,
+        };
+        if !created {
+            self.remove_did(&did, index.0, deposit.0.saturating_sub(initial_balance.0));
+            Promise::new(payer).transfer(deposit.0);
+        }
+
+        let log_message = format!(
+            "on_account_created, account: {}, created: {}",
+            &account_id, created
+        );
+        env::log(log_message.as_bytes());
+        created
+    }
+}
+
+impl DID {
+    /// remove the DID and everything stored for it, and take `storage_balance`, the part paid by the payer, out of its storage balance.
+    /// the slot of the DID at `index` is replaced by a tombstone, so the other DIDs keep their index.
+    fn remove_did(&mut self, did: &String, index: u64, storage_balance: u128) {
+        let record = match self.record.remove(did) {
+            Some(v) => v,
+            None => env::panic(b"on_account_created, did doesn't exist"),
+        };
+        for i in 0..record.key_count {
+            if let Some(public_key) = self.public_key.remove(&key_id(did, i)) {
+                self.unindex_key(public_key.public_key(), did);
+                self.key_lookup
+                    .remove(&key_lookup_key(did, public_key.public_key()));
+            }
+        }
+        for version in 1..=record.version {
+            if let Some(v) = self.version_record.remove(&version_key(did, version)) {
+                for (id, service_version) in v.services.iter() {
+                    self.service.remove(&service_key(did, id, *service_version));
+                }
+            }
+            self.operation.remove(&operation_key(did, version - 1));
+        }
+        for controller in record.controllers.iter() {
+            self.unindex_controller(controller, did);
+        }
+        if let Some(account) = record.bound_account.as_ref() {
+            self.bound_did.remove(account);
+        }
+        if self.dids.get(index).as_ref() == Some(did) {
+            self.dids.replace(index, &REMOVED_DID.to_string());
+        }
+        if let Some(mut storage_account) = self.storage_account.get(did) {
+            storage_account.total = storage_account.total.saturating_sub(storage_balance);
+            storage_account.used_bytes = 0;
+            if storage_account.total == 0 {
+                self.storage_account.remove(did);
+            } else {
+                self.storage_account.insert(did, &storage_account);
+            }
+        }
+    }
+}
@@ -0,0 +1,250 @@
+//! integrity
+//!
+//! The record of a DID references its keys and services by index and id, so the references must stay consistent
+//! for the document to be rendered. Every update checks the invariants of the record itself, `check_integrity`
+//! checks the references to the keys, services and indexes as well, and `repair_did` restores them.
+
+use super::*;
+
+/// the invariants of the record which can be checked without reading the keys and services.
+pub fn record_violations(record: &DidRecord) -> Vec<String> {
+    let mut violations = vec![];
+    for (i, index) in record.authentication.iter().enumerate() {
+        if *index >= record.key_count {
+            violations.push(format!("authentication key {} doesn't exist", index));
+        }
+        if record.authentication[..i].contains(index) {
+            violations.push(format!("authentication key {} is duplicated", index));
+        }
+    }
+    for (i, (id, _)) in record.services.iter().enumerate() {
+        if record.services[..i].iter().any(|(x, _)| x == id) {
+            violations.push(format!("service {} is duplicated", id));
+        }
+    }
+    for (i, controller) in record.controllers.iter().enumerate() {
+        if record.controllers[..i].contains(controller) {
+            violations.push(format!("controller {} is duplicated", controller));
+        }
+    }
+    for (i, context) in record.contexts.iter().enumerate() {
+        if record.contexts[..i].contains(context) {
+            violations.push(format!("context {} is duplicated", context));
+        }
+    }
+    for (i, uri) in record.also_known_as.iter().enumerate() {
+        if record.also_known_as[..i].contains(uri) {
+            violations.push(format!("alsoKnownAs {} is duplicated", uri));
+        }
+    }
+    if !record.is_valid()
+        && (!record.contexts.is_empty()
+            || !record.authentication.is_empty()
+            || !record.controllers.is_empty()
+            || !record.services.is_empty()
+            || !record.also_known_as.is_empty())
+    {
+        violations.push(
+            "deactivated DID has contexts, authentication, controllers, services or alsoKnownAs"
+                .to_string(),
+        );
+    }
+    violations
+}
+
+#[near_bindgen]
+impl DID {
+    /// check_integrity
+    ///
+    /// this method query the invariant violations of the DID, an empty list means the DID is consistent.
+    ///
+    /// the authentication keys must exist, be valid and be authentication keys, every valid authentication key must be
+    /// in the authentication list, the services must exist, and the key and controller indexes must reference the DID.
+    ///
+    pub fn check_integrity(&self, did: String) -> Vec<String> {
+        let record = match self.record.get(&did) {
+            Some(v) => v,
+            None => return vec!["DID doesn't exist".to_string()],
+        };
+        let mut violations = record_violations(&record);
+        for i in 0..record.key_count {
+            let public_key = match self.public_key.get(&key_id(&did, i)) {
+                Some(v) => v,
+                None => {
+                    violations.push(format!("key {} doesn't exist", i));
+                    continue;
+                }
+            };
+            let in_authentication = record.authentication.contains(&i);
+            if in_authentication && public_key.is_deactivated() {
+                violations.push(format!("authentication key {} is deactivated", i));
+            }
+            if in_authentication && !public_key.is_authentication() {
+                violations.push(format!("authentication key {} is not authentication", i));
+            }
+            if record.is_valid()
+                && !in_authentication
+                && public_key.is_authentication()
+                && !public_key.is_deactivated()
+            {
+                violations.push(format!("key {} is not in authentication", i));
+            }
+            let indexed = self
+                .key_index
+                .get(public_key.public_key())
+                .unwrap_or(vec![])
+                .contains(&(did.clone(), i));
+            if record.is_valid() && !indexed {
+                violations.push(format!("key {} is not indexed", i));
+            }
+            if self
+                .key_lookup
+                .get(&key_lookup_key(&did, public_key.public_key()))
+                != Some(i)
+            {
+                violations.push(format!("key {} is not in the key lookup", i));
+            }
+        }
+        for (id, version) in record.services.iter() {
+            if self.service.get(&service_key(&did, id, *version)).is_none() {
+                violations.push(format!("service {} doesn't exist", id));
+            }
+        }
+        if let Some(account) = record.bound_account.as_ref() {
+            if record.is_valid() && self.bound_did.get(account).as_ref() != Some(&did) {
+                violations.push(format!("account {} is not bound", account));
+            }
+        }
+        for controller in record.controllers.iter() {
+            let indexed = self
+                .controlled
+                .get(controller)
+                .unwrap_or(vec![])
+                .contains(&did);
+            if !indexed {
+                violations.push(format!("controller {} is not indexed", controller));
+            }
+        }
+        violations
+    }
+
+    /// repair_did
+    ///
+    /// this method will restore the invariants of the DID, the references which can't be resolved are removed.
+    /// only the owner and the admins have the right to invoke this method.
+    ///
+    /// the repair is recorded as a new version of the DID, it's not charged to the storage balance of the DID.
+    ///
+    /// log information: EVENT_JSON:{"standard":"did-near","version":"1.0.0","event":"repair_did","data":[{"did":"did:near:abcde.testnet","violations":["authentication key 1 is deactivated"]}]}
+    ///
+    pub fn repair_did(&mut self, did: String) {
+        self.check_admin();
+        let violations = self.check_integrity(did.clone());
+        if violations.is_empty() {
+            env::panic(b"repair_did, DID is consistent")
+        }
+        let record = self.record.get(&did).unwrap();
+        let record = self.repair_record(&did, record);
+        self.save_record(&did, record, "repair_did", None);
+        emit_event(
+            "repair_did",
+            serde_json::json!({ "did": did, "violations": violations }),
+        );
+    }
+}
+
+impl DID {
+    /// rebuild the references of the record from the keys and services, and index them again.
+    pub(crate) fn repair_record(&mut self, did: &String, mut record: DidRecord) -> DidRecord {
+        let mut authentication = vec![];
+        for i in record.authentication.iter() {
+            if let Some(public_key) = self.public_key.get(&key_id(did, *i)) {
+                if public_key.is_authentication()
+                    && !public_key.is_deactivated()
+                    && !authentication.contains(i)
+                {
+                    authentication.push(*i);
+                }
+            }
+        }
+        let mut services: Vec<(String, u64)> = vec![];
+        for (id, version) in record.services.iter() {
+            if self.service.get(&service_key(did, id, *version)).is_some()
+                && !services.iter().any(|(x, _)| x == id)
+            {
+                services.push((id.clone(), *version));
+            }
+        }
+        let mut controllers: Vec<String> = vec![];
+        for controller in record.controllers.iter() {
+            if !controllers.contains(controller) {
+                controllers.push(controller.clone());
+            }
+        }
+        let mut contexts: Vec<String> = vec![];
+        for context in record.contexts.iter() {
+            if !contexts.contains(context) {
+                contexts.push(context.clone());
+            }
+        }
+        let mut also_known_as: Vec<String> = vec![];
+        for uri in record.also_known_as.iter() {
+            if !also_known_as.contains(uri) {
+                also_known_as.push(uri.clone());
+            }
+        }
+
+        for i in 0..record.key_count {
+            let public_key = match self.public_key.get(&key_id(did, i)) {
+                Some(v) => v,
+                None => continue,
+            };
+            self.key_lookup
+                .insert(&key_lookup_key(did, public_key.public_key()), &i);
+            if record.is_valid() {
+                if public_key.is_authentication()
+                    && !public_key.is_deactivated()
+                    && !authentication.contains(&i)
+                {
+                    authentication.push(i);
+                }
+                self.index_key(public_key.public_key(), did, i);
+            } else {
+                self.unindex_key(public_key.public_key(), did);
+            }
+        }
+        if record.is_valid() {
+            for controller in controllers.iter() {
+                self.index_controller(controller, did);
+            }
+            if let Some(account) = record.bound_account.as_ref() {
+                self.bound_did.insert(account, did);
+            }
+            record.authentication = authentication;
+            record.services = services;
+            record.controllers = controllers;
+            record.contexts = contexts;
+            record.also_known_as = also_known_as;
+        } else {
+            for controller in record.controllers.iter() {
+                self.unindex_controller(controller, did);
+            }
+            record.authentication = vec![];
+            record.services = vec![];
+            record.controllers = vec![];
+            record.contexts = vec![];
+            record.also_known_as = vec![];
+        }
+        record
+    }
+}
+
+#[cfg(test)]
+impl DID {
+    /// change the stored record of the DID without checking it, as the state written by a previous version could be.
+    pub fn corrupt_record(&mut self, did: &String, f: impl FnOnce(&mut DidRecord)) {
+        let mut record = self.record.get(did).unwrap();
+        f(&mut record);
+        self.record.insert(did, &record);
+    }
+}
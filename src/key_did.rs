@@ -0,0 +1,166 @@
+//! key-derived DIDs
+//!
+//! The method-specific id of a key-derived DID is the hex of an Ed25519 public key, the same as the id of the
+//! NEAR implicit account of the key. Anyone can register it with a signature from the key, so the user doesn't
+//! need a funded account, and the implicit account manages it once it's funded. A key-derived DID can also be bound
+//! to a named account, which manages it by its own key afterwards.
+
+use super::*;
+use ed25519_dalek::Verifier;
+use std::convert::TryFrom;
+
+/// the length of a NEAR Ed25519 public key, the key type byte followed by the key.
+const ED25519_KEY_LENGTH: usize = 33;
+
+/// the method-specific id of the DID derived from `pk`, None if it's not an Ed25519 key.
+pub fn key_did_id(pk: &[u8]) -> Option<String> {
+    if pk.len() != ED25519_KEY_LENGTH || pk[0] != 0 {
+        return None;
+    }
+    Some(pk[1..].iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// return true if the method-specific id is the hex of an Ed25519 public key.
+pub fn is_key_did_id(id: &str) -> bool {
+    id.len() == (ED25519_KEY_LENGTH - 1) * 2
+        && id
+            .chars()
+            .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+}
+
+/// verify the Ed25519 signature of the message by `pk`, a NEAR Ed25519 public key.
+pub fn verify_ed25519(pk: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    if key_did_id(pk).is_none() {
+        return false;
+    }
+    let public_key = match ed25519_dalek::PublicKey::from_bytes(&pk[1..]) {
+        Ok(v) => v,
+        Err(_) => return false,
+    };
+    let signature = match ed25519_dalek::Signature::try_from(signature) {
+        Ok(v) => v,
+        Err(_) => return false,
+    };
+    public_key.verify(message, &signature).is_ok()
+}
+
+/// the message signed to register the key-derived DID, it's bound to the contract so it can't be replayed on another one.
+pub fn reg_did_message(contract_id: &str, did: &str) -> String {
+    format!("{}:reg_did_using_key:{}", contract_id, did)
+}
+
+/// the message signed to bind the key-derived DID to the account.
+pub fn bind_account_message(contract_id: &str, did: &str, account_id: &str) -> String {
+    format!("{}:bind_account:{}:{}", contract_id, did, account_id)
+}
+
+#[near_bindgen]
+impl DID {
+    /// reg_did_using_key
+    ///
+    /// this method will register the DID derived from the Ed25519 public key `pk`, such as "did:near:<hex of the key>".
+    /// anyone can invoke this method, `signature` is the signature of "<contract account>:reg_did_using_key:<DID>" by the key.
+    ///
+    /// the key is the first public key and authentication key of the DID, the implicit account of the key manages the DID.
+    ///
+    /// the attached deposit is added to the storage balance of the DID, it must cover the storage used by the DID.
+    ///
+    /// log information: "reg_did_using_key: did:near:8b3f..."
+    pub fn reg_did_using_key(&mut self, pk: Vec<u8>, signature: Vec<u8>) {
+        self.check_not_paused("reg_did_using_key", PauseGroup::Registration);
+        let initial_storage = env::storage_usage();
+        let did = match key_did_id(&pk) {
+            Some(id) => self.config.gen_did(&id),
+            None => env::panic(b"reg_did_using_key, pk is not an ed25519 key"),
+        };
+
+        if self.record.get(&did).is_some() {
+            env::panic(b"reg_did_using_key, did exists")
+        }
+        let message = reg_did_message(&env::current_account_id(), &did);
+        if !verify_ed25519(&pk, message.as_bytes(), &signature) {
+            env::panic(b"reg_did_using_key, invalid signature")
+        }
+
+        let mut record = DidRecord::new(env::block_timestamp());
+        let index = self.push_key(&did, &mut record, PublicKey::new_pk_and_auth(&did, pk));
+        record.authentication.push(index);
+        self.dids.push(&did);
+        self.save_record(&did, record, "reg_did_using_key", None);
+        self.charge_storage(&did, initial_storage);
+
+        let log_message = format!("reg_did_using_key: {}", &did);
+        env::log(log_message.as_bytes());
+    }
+
+    /// bind_account
+    ///
+    /// this method will bind the key-derived DID to the signer account, the account manages the DID instead of its own DID afterwards.
+    /// the account must not have a DID. `pk` is an Ed25519 authentication key of the DID, `signature` is the signature of
+    /// "<contract account>:bind_account:<DID>:<account>" by it. the signer key is added as an authentication key of the DID.
+    ///
+    /// log information: "bind_account, did: did:near:8b3f..., account: alice.testnet"
+    ///
+    pub fn bind_account(&mut self, did: String, pk: Vec<u8>, signature: Vec<u8>) {
+        self.check_not_paused("bind_account", PauseGroup::Registration);
+        let initial_storage = env::storage_usage();
+        let account_id = env::signer_account_id();
+        let account_pk = env::signer_account_pk();
+
+        let mut record = self.get_valid_record(&did);
+        if !did
+            .strip_prefix(&self.config.did_prefix())
+            .map(is_key_did_id)
+            .unwrap_or(false)
+        {
+            env::panic(b"bind_account, did is not derived from a key")
+        }
+        if record.bound_account.is_some() {
+            env::panic(b"bind_account, did is bound")
+        }
+        if self.bound_did.get(&account_id).is_some() {
+            env::panic(b"bind_account, account is bound")
+        }
+        if self.record.get(&self.config.gen_did(&account_id)).is_some() {
+            env::panic(b"bind_account, account has a did")
+        }
+        match self.find_key(&did, &pk) {
+            Some((_, public_key)) if public_key.has_relationship(RELATIONSHIP_AUTHENTICATION) => (),
+            _ => env::panic(b"bind_account, pk is not authentication"),
+        }
+        let message = bind_account_message(&env::current_account_id(), &did, &account_id);
+        if !verify_ed25519(&pk, message.as_bytes(), &signature) {
+            env::panic(b"bind_account, invalid signature")
+        }
+
+        match self.find_key(&did, &account_pk) {
+            Some((_, public_key)) if public_key.has_relationship(RELATIONSHIP_AUTHENTICATION) => (),
+            Some(_) => env::panic(b"bind_account, signer pk is not authentication"),
+            None => {
+                self.check_key_limits("bind_account", &record, &account_pk, &did);
+                let index = self.push_key(
+                    &did,
+                    &mut record,
+                    PublicKey::new_pk_and_auth(&did, account_pk),
+                );
+                record.authentication.push(index);
+            }
+        }
+        self.bound_did.insert(&account_id, &did);
+        record.bound_account = Some(account_id.clone());
+        record.updated = env::block_timestamp();
+        self.save_record(&did, record, "bind_account", None);
+        self.charge_storage(&did, initial_storage);
+
+        let log_message = format!("bind_account, did: {}, account: {}", &did, &account_id);
+        env::log(log_message.as_bytes());
+    }
+
+    /// get_bound_did
+    ///
+    /// this method query the key-derived DID bound to the account.
+    ///
+    pub fn get_bound_did(&self, account_id: String) -> Option<String> {
+        self.bound_did.get(&account_id)
+    }
+}
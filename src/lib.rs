@@ -9,12 +9,42 @@
 //!
 
 use borsh::{BorshDeserialize, BorshSerialize};
-use near_sdk::collections::UnorderedMap;
-use near_sdk::{env, near_bindgen};
+use near_sdk::collections::{LookupMap, Vector};
+use near_sdk::json_types::U128;
+use near_sdk::{env, near_bindgen, Promise};
 use serde::{Deserialize, Serialize};
 
+mod also_known_as;
 mod basic;
+mod document;
+mod event;
+mod factory;
+mod integrity;
+mod key_did;
+mod migrate;
+mod patch;
+mod pause;
+mod resolve;
+mod roles;
+mod storage;
+mod upgrade;
+mod validate;
+use also_known_as::*;
+use base58::*;
 use basic::*;
+use document::*;
+use event::*;
+use factory::*;
+use integrity::*;
+use key_did::*;
+use migrate::*;
+use patch::*;
+use pause::*;
+use resolve::*;
+use roles::*;
+use storage::*;
+use upgrade::*;
+use validate::*;
 
 #[cfg(not(target_arch = "wasm32"))]
 #[cfg(test)]
@@ -25,77 +55,226 @@ static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 
 const DEFAULT_CONTEXT1: &'static str = "https://www.w3.org/ns/did/v1";
 const DEFAULT_CONTEXT2: &'static str = "https://www.near.org/did/v1";
+/// the tombstone left in `dids` by a DID removed because its account couldn't be created.
+const REMOVED_DID: &'static str = "";
 
 #[near_bindgen]
-#[derive(Default, BorshDeserialize, BorshSerialize)]
+#[derive(BorshDeserialize, BorshSerialize)]
 pub struct DID {
-    /// Status is used to store the state of DID. There are two states of DID, valid and invalid.
-    pub status: UnorderedMap<String, Status>,
-    /// context.
-    pub contexts: UnorderedMap<String, Vec<String>>,
-    /// It is used to store all public key information corresponding to this DID
-    pub public_key: UnorderedMap<String, PublicKeyList>,
-    pub authentication: UnorderedMap<String, Vec<u32>>, //
-    /// It is used to store all controller information corresponding to this DID. The controller has the authority to update the information of this did.
-    pub controller: UnorderedMap<String, Vec<String>>,
-    pub service: UnorderedMap<String, Vec<Service>>,
-    /// Used to store the creation time of DID
-    pub created: UnorderedMap<String, u64>,
-    /// Used to store the update time of DID
-    pub updated: UnorderedMap<String, u64>,
+    /// the version of the state layout, `migrate` upgrades the state of a previous version.
+    pub state_version: u32,
+    /// Used to store all the registered DIDs in registration order.
+    pub dids: Vector<String>,
+    /// Used to store the record of DID: status, contexts, controllers, the references to its keys and services, and the times.
+    pub record: LookupMap<String, DidRecord>,
+    /// It is used to store all public key information, the key is the key id "did#keys-n".
+    pub public_key: LookupMap<String, PublicKey>,
+    /// Used to store all service information, the key is "did#id?versionId=n", n is the version in which the service was written.
+    pub service: LookupMap<String, Service>,
+    /// Used to store the record of every version of DID, the key is "did?versionId=n".
+    pub version_record: LookupMap<String, DidRecord>,
+    /// Used to store the operation log of DID, the key is "did?operation=n", n starts from 0.
+    pub operation: LookupMap<String, Operation>,
+    /// Used to find the DIDs which reference a public key and the index of the key in them, the key is the public key.
+    /// it's only read by `find_dids_by_key`, any DID can add an entry for any public key.
+    pub key_index: LookupMap<Vec<u8>, Vec<(String, u32)>>,
+    /// Used to find the index of a public key in a DID, the key is "did?publicKey=<base58 public key>".
+    pub key_lookup: LookupMap<String, u32>,
+    /// Used to find the DIDs controlled by a controller, the key is the controller.
+    pub controlled: LookupMap<String, Vec<String>>,
+    /// Used to find the key-derived DID bound to an account, the key is the account.
+    pub bound_did: LookupMap<String, String>,
+    /// the contract-level configuration, such as the allowed service endpoint schemes.
+    pub config: Config,
+    /// the owner, admins and operators of the contract.
+    pub roles: Roles,
+    /// the groups of mutations which are paused.
+    pub paused: Vec<PauseGroup>,
+    /// the timelock and the approval threshold of upgrades.
+    pub upgrade: UpgradeConfig,
+    /// the code staged for the next upgrade, the code itself is stored under its own key.
+    pub staged_code: Option<StagedCode>,
+    /// Used to store the storage deposit of DID and the bytes used by it.
+    pub storage_account: LookupMap<String, StorageAccount>,
+}
+
+impl Default for DID {
+    fn default() -> Self {
+        env::panic(b"contract is not initialized")
+    }
+}
+
+impl DID {
+    fn init_state(owner_id: String, config: Config) -> Self {
+        DID {
+            state_version: STATE_VERSION,
+            dids: Vector::new(b"dids".to_vec()),
+            record: LookupMap::new(b"record".to_vec()),
+            public_key: LookupMap::new(b"public_key".to_vec()),
+            service: LookupMap::new(b"service".to_vec()),
+            version_record: LookupMap::new(b"version_record".to_vec()),
+            operation: LookupMap::new(b"operation".to_vec()),
+            key_index: LookupMap::new(b"key_index".to_vec()),
+            key_lookup: LookupMap::new(b"key_lookup".to_vec()),
+            controlled: LookupMap::new(b"controlled".to_vec()),
+            bound_did: LookupMap::new(b"bound_did".to_vec()),
+            config,
+            roles: Roles::new(owner_id),
+            paused: vec![],
+            upgrade: UpgradeConfig::default(),
+            staged_code: None,
+            storage_account: LookupMap::new(b"storage_account".to_vec()),
+        }
+    }
+
+    /// check the configuration of a new state, the default is used if `config` is None.
+    fn init_config(method: &str, config: Option<Config>) -> Config {
+        let mut config = config.unwrap_or_default();
+        assert_valid(
+            method,
+            check_did_prefix(&config.method_prefix, &config.network),
+        );
+        config.endpoint_schemes = config
+            .endpoint_schemes
+            .iter()
+            .map(|x| x.to_ascii_lowercase())
+            .collect();
+        config
+    }
 }
 
 #[near_bindgen]
 impl DID {
+    /// new
+    ///
+    /// this method will initialize the contract with its owner, the default configuration is used if `config` is None.
+    ///
+    /// the method prefix and the network segment of the DIDs can't be changed after the initialization,
+    /// a sandbox deployment can use its own network segment, such as "did:near:sandbox:alice.test.near", so that its DIDs don't collide with the real ones.
+    ///
+    /// log information: EVENT_JSON:{"standard":"did-near","version":"1.0.0","event":"init","data":[{"owner_id":"alice.testnet"}]}
+    ///
+    #[init]
+    pub fn new(owner_id: String, config: Option<Config>) -> Self {
+        if env::state_exists() {
+            env::panic(b"new, contract is already initialized")
+        }
+        let config = DID::init_config("new", config);
+        emit_event("init", serde_json::json!({ "owner_id": owner_id }));
+        DID::init_state(owner_id, config)
+    }
+
     /// register DID
     ///
     /// this method will store DID information on the chain
     ///
+    /// the attached deposit is added to the storage balance of the DID, it must cover the storage used by the DID.
+    ///
     /// this method will output log information in the following format,
     ///
     /// log information: "reg_did_using_account: did:near:abcde.testnet"
     pub fn reg_did_using_account(&mut self) {
+        self.check_not_paused("reg_did_using_account", PauseGroup::Registration);
+        let initial_storage = env::storage_usage();
         let account_id = env::signer_account_id();
         let account_pk = env::signer_account_pk();
-        let did = gen_did(&account_id);
+        let did = self.config.gen_did(&account_id);
 
-        let status = self.status.get(&did);
-        assert!(status.is_none());
+        if self.record.get(&did).is_some() {
+            env::panic(b"reg_did_using_account, did exists")
+        }
+        if self.bound_did.get(&account_id).is_some() {
+            env::panic(b"reg_did_using_account, account is bound")
+        }
+        let limits = &self.config.limits;
+        assert_valid(
+            "reg_did_using_account",
+            check_length("public key", account_pk.len(), limits.max_public_key_length),
+        );
 
-        self.status.insert(&did, &Status::VALID);
-        self.public_key
-            .insert(&did, &PublicKeyList::new_default(&did, account_pk));
-        let index: u32 = 0;
-        self.authentication.insert(&did, &vec![index]);
-        self.created.insert(&did, &env::block_timestamp());
+        let mut record = DidRecord::new(env::block_timestamp());
+        let index = self.push_key(
+            &did,
+            &mut record,
+            PublicKey::new_pk_and_auth(&did, account_pk),
+        );
+        record.authentication.push(index);
+        self.dids.push(&did);
+        self.save_record(&did, record, "reg_did_using_account", None);
+        self.charge_storage(&did, initial_storage);
 
         let log_message = format!("reg_did_using_account: {}", &did);
         env::log(log_message.as_bytes());
     }
 
+    /// reg_did_using_predecessor
+    ///
+    /// this method will register the DID of the predecessor account, the DID is authorized by the predecessor account
+    /// instead of the key signing the transaction, so that a contract such as a DAO or a multisig can own it and act as a controller.
+    ///
+    /// the DID has no keys when it's registered, the keys added later are not used to authorize the DID,
+    /// so they can't be authentication keys.
+    ///
+    /// the attached deposit is added to the storage balance of the DID, it must cover the storage used by the DID.
+    ///
+    /// log information: "reg_did_using_predecessor: did:near:dao.testnet"
+    pub fn reg_did_using_predecessor(&mut self) {
+        self.check_not_paused("reg_did_using_predecessor", PauseGroup::Registration);
+        let initial_storage = env::storage_usage();
+        let did = self.config.gen_did(&env::predecessor_account_id());
+
+        if self.record.get(&did).is_some() {
+            env::panic(b"reg_did_using_predecessor, did exists")
+        }
+
+        let mut record = DidRecord::new(env::block_timestamp());
+        record.auth_mode = AuthMode::Predecessor;
+        self.dids.push(&did);
+        self.save_record(&did, record, "reg_did_using_predecessor", None);
+        self.charge_storage(&did, initial_storage);
+
+        let log_message = format!("reg_did_using_predecessor: {}", &did);
+        env::log(log_message.as_bytes());
+    }
+
     /// deactivate_did DID
     ///
     /// this method will update DID to deactive status, this means the DID is a invalid.
     ///
+    /// the storage freed by the DID is refunded to the signer together with the rest of the storage balance.
+    ///
     /// log information: "deactivate_did: did:near:abcde.testnet"
     pub fn deactivate_did(&mut self) {
-        let account_id = env::signer_account_id();
-        let account_pk = env::signer_account_pk();
-        let did = gen_did(&account_id);
-
-        let status = self.status.get(&did);
-        assert!(status.is_some());
-        let public_key_list = self.public_key.get(&did).unwrap();
-        public_key_list.check_pk_access(&account_pk);
-
-        self.status.insert(&did, &Status::DEACTIVATED);
-        self.contexts.remove(&did);
-        self.public_key.remove(&did);
-        self.authentication.remove(&did);
-        self.controller.remove(&did);
-        self.service.remove(&did);
-        self.created.remove(&did);
-        self.updated.remove(&did);
+        self.check_not_paused("deactivate_did", PauseGroup::Registration);
+        let initial_storage = env::storage_usage();
+        let did = self.caller_did();
+
+        let mut record = self.get_valid_record(&did);
+        self.check_access(&did, &record);
+        let account_id = self.caller_account(&record);
+
+        for i in 0..record.key_count {
+            let public_key = self.public_key.get(&key_id(&did, i)).unwrap();
+            self.unindex_key(public_key.public_key(), &did);
+        }
+        for controller in record.controllers.iter() {
+            self.unindex_controller(controller, &did);
+        }
+        // the account can register or bind another DID
+        if let Some(account) = record.bound_account.as_ref() {
+            self.bound_did.remove(account);
+        }
+        // keys and services are kept, the previous versions of the document reference them
+        record.status = Status::DEACTIVATED;
+        record.contexts = vec![];
+        record.authentication = vec![];
+        record.controllers = vec![];
+        record.services = vec![];
+        record.also_known_as = vec![];
+        record.updated = env::block_timestamp();
+        self.save_record(&did, record, "deactivate_did", None);
+        self.charge_storage(&did, initial_storage);
+        self.refund_storage(&did, &account_id);
 
         let log_message = format!("deactivate_did: {}", &did);
         env::log(log_message.as_bytes());
@@ -110,24 +289,37 @@ impl DID {
     /// log information: "add_controller, did: did:near:abcde.testnet, controller: did:near:abcdefg.testnet"
     ///
     pub fn add_controller(&mut self, controller: String) {
-        let account_id = env::signer_account_id();
-        let account_pk = env::signer_account_pk();
-        let did = gen_did(&account_id);
+        self.check_not_paused("add_controller", PauseGroup::Controllers);
+        let initial_storage = env::storage_usage();
+        let did = self.caller_did();
 
         let log_message = format!("add_controller, did: {}, controller: {}", &did, &controller);
 
-        self.check_did_status(&did);
-        let public_key_list = self.public_key.get(&did).unwrap();
-        public_key_list.check_pk_access(&account_pk);
-        check_did(&controller);
-        let mut controller_list = self.controller.get(&did).unwrap_or(vec![]);
-        if controller_list.contains(&controller) {
+        let mut record = self.get_valid_record(&did);
+        self.check_access(&did, &record);
+        self.config.check_did(&controller);
+        if record.controllers.contains(&controller) {
             env::panic(b"add_controller, controller exists")
         };
+        let limits = &self.config.limits;
+        assert_valid(
+            "add_controller",
+            check_length("controller", controller.len(), limits.max_did_length),
+        );
+        assert_valid(
+            "add_controller",
+            check_count(
+                "controllers",
+                record.controllers.len() + 1,
+                limits.max_controllers,
+            ),
+        );
 
-        controller_list.push(controller);
-        self.controller.insert(&did, &controller_list);
-        self.updated.insert(&did, &env::block_timestamp());
+        self.index_controller(&controller, &did);
+        record.controllers.push(controller);
+        record.updated = env::block_timestamp();
+        self.save_record(&did, record, "add_controller", None);
+        self.charge_storage(&did, initial_storage);
         env::log(log_message.as_bytes());
     }
 
@@ -140,22 +332,22 @@ impl DID {
     /// log information: "remove_controller, did: did:near:abcde.testnet, controller: did:near:abcdefg.testnet"
     ///
     pub fn remove_controller(&mut self, controller: String) {
-        let account_id = env::signer_account_id();
-        let account_pk = env::signer_account_pk();
-        let did = gen_did(&account_id);
+        self.check_not_paused("remove_controller", PauseGroup::Controllers);
+        let initial_storage = env::storage_usage();
+        let did = self.caller_did();
 
-        self.check_did_status(&did);
-        let public_key_list = self.public_key.get(&did).unwrap();
-        public_key_list.check_pk_access(&account_pk);
+        let mut record = self.get_valid_record(&did);
+        self.check_access(&did, &record);
 
-        let mut controller_list = self.controller.get(&did).unwrap();
-        let index = controller_list
-            .iter()
-            .position(|x| x == &controller)
-            .unwrap();
-        controller_list.remove(index);
-        self.controller.insert(&did, &controller_list);
-        self.updated.insert(&did, &env::block_timestamp());
+        let index = match record.controllers.iter().position(|x| x == &controller) {
+            Some(v) => v,
+            None => env::panic(b"remove_controller, controller doesn't exist"),
+        };
+        record.controllers.remove(index);
+        self.unindex_controller(&controller, &did);
+        record.updated = env::block_timestamp();
+        self.save_record(&did, record, "remove_controller", None);
+        self.charge_storage(&did, initial_storage);
 
         let log_message = format!(
             "remove_controller, did:{}, controller: {}",
@@ -173,26 +365,27 @@ impl DID {
     /// log information: "add_key, did: did:near:abcde.testnet, public key: , controller: did:near:abcdefg.testnet"
     ///
     pub fn add_key(&mut self, pk: Vec<u8>, controller: String) {
-        let account_id = env::signer_account_id();
-        let account_pk = env::signer_account_pk();
-        let did = gen_did(&account_id);
+        self.check_not_paused("add_key", PauseGroup::Keys);
+        let initial_storage = env::storage_usage();
+        let did = self.caller_did();
 
         let log_message = format!(
             "add_key, did:{}, public key: {:?}, controller: {}",
             &did, &pk, &controller
         );
 
-        self.check_did_status(&did);
-        check_did(&controller);
-        let mut public_key_list = self.public_key.get(&did).unwrap();
-        public_key_list.check_pk_access(&account_pk);
-        if public_key_list.pk_exist(&pk) {
+        let mut record = self.get_valid_record(&did);
+        self.config.check_did(&controller);
+        self.check_access(&did, &record);
+        if self.find_key(&did, &pk).is_some() {
             env::panic(b"add_key, pk exists")
         }
+        self.check_key_limits("add_key", &record, &pk, &controller);
 
-        public_key_list.push(PublicKey::new_pk(&controller, pk));
-        self.public_key.insert(&did, &public_key_list);
-        self.updated.insert(&did, &env::block_timestamp());
+        self.push_key(&did, &mut record, PublicKey::new_pk(&controller, pk));
+        record.updated = env::block_timestamp();
+        self.save_record(&did, record, "add_key", None);
+        self.charge_storage(&did, initial_storage);
 
         env::log(log_message.as_bytes());
     }
@@ -206,17 +399,24 @@ impl DID {
     /// log information: "deactivate_key, did: did:near:abcde.testnet, public key: "
     ///
     pub fn deactivate_key(&mut self, pk: Vec<u8>) {
-        let account_id = env::signer_account_id();
-        let account_pk = env::signer_account_pk();
-        let did = gen_did(&account_id);
+        self.check_not_paused("deactivate_key", PauseGroup::Keys);
+        let initial_storage = env::storage_usage();
+        let did = self.caller_did();
 
-        self.check_did_status(&did);
-        let mut public_key_list = self.public_key.get(&did).unwrap();
-        public_key_list.check_pk_access(&account_pk);
+        let mut record = self.get_valid_record(&did);
+        self.check_access(&did, &record);
 
-        public_key_list.deactivate_pk(&pk);
-        self.public_key.insert(&did, &public_key_list);
-        self.updated.insert(&did, &env::block_timestamp());
+        let (index, mut public_key) = match self.find_key(&did, &pk) {
+            Some(v) => v,
+            None => env::panic(b"deactivate_pk, pk doesn't exist"),
+        };
+        public_key.deactivate();
+        self.public_key.insert(&key_id(&did, index), &public_key);
+        // a deactivated key can't be used for authentication
+        record.authentication.retain(|x| x != &index);
+        record.updated = env::block_timestamp();
+        self.save_record(&did, record, "deactivate_key", None);
+        self.charge_storage(&did, initial_storage);
 
         let log_message = format!("deactivate_key, did:{}, public key: {:?}", &did, pk);
         env::log(log_message.as_bytes());
@@ -233,30 +433,29 @@ impl DID {
     /// log information: "add_new_auth_key, did: did:near:abcde.testnet, public key: ,controller: did:near:abcdefg.testnet"
     ///
     pub fn add_new_auth_key(&mut self, pk: Vec<u8>, controller: String) {
-        let account_id = env::signer_account_id();
-        let account_pk = env::signer_account_pk();
-        let did = gen_did(&account_id);
-
-        self.check_did_status(&did);
-        check_did(&controller);
-        let mut public_key_list = self.public_key.get(&did).unwrap();
-        public_key_list.check_pk_access(&account_pk);
-        if public_key_list.pk_exist(&pk) {
+        self.check_not_paused("add_new_auth_key", PauseGroup::Keys);
+        let initial_storage = env::storage_usage();
+        let did = self.caller_did();
+
+        let mut record = self.get_valid_record(&did);
+        self.config.check_did(&controller);
+        self.check_access(&did, &record);
+        self.check_auth_key_allowed("add_new_auth_key", &record);
+        if self.find_key(&did, &pk).is_some() {
             env::panic(b"add_new_auth_key, pk exists")
         }
+        self.check_key_limits("add_new_auth_key", &record, &pk, &controller);
 
         let log_message = format!(
             "add_new_auth_key, did:{}, public key: {:?}, controller: {}",
             &did, &pk, &controller
         );
 
-        public_key_list.push(PublicKey::new_auth(&controller, pk));
-        self.public_key.insert(&did, &public_key_list);
-        let mut authentication_list = self.authentication.get(&did).unwrap();
-        let index: u32 = (public_key_list.len() - 1) as u32;
-        authentication_list.push(index);
-        self.authentication.insert(&did, &authentication_list);
-        self.updated.insert(&did, &env::block_timestamp());
+        let index = self.push_key(&did, &mut record, PublicKey::new_auth(&controller, pk));
+        record.authentication.push(index);
+        record.updated = env::block_timestamp();
+        self.save_record(&did, record, "add_new_auth_key", None);
+        self.charge_storage(&did, initial_storage);
 
         env::log(log_message.as_bytes());
     }
@@ -270,20 +469,18 @@ impl DID {
     /// log information: "set_auth_key, did: did:near:abcde.testnet, public key: "
     ///
     pub fn set_auth_key(&mut self, pk: Vec<u8>) {
-        let account_id = env::signer_account_id();
-        let account_pk = env::signer_account_pk();
-        let did = gen_did(&account_id);
+        self.check_not_paused("set_auth_key", PauseGroup::Keys);
+        let initial_storage = env::storage_usage();
+        let did = self.caller_did();
 
-        self.check_did_status(&did);
-        let mut public_key_list = self.public_key.get(&did).unwrap();
-        public_key_list.check_pk_access(&account_pk);
+        let mut record = self.get_valid_record(&did);
+        self.check_access(&did, &record);
+        self.check_auth_key_allowed("set_auth_key", &record);
 
-        let index = public_key_list.set_pk_auth(&pk);
-        self.public_key.insert(&did, &public_key_list);
-        let mut authentication_list = self.authentication.get(&did).unwrap();
-        authentication_list.push(index as u32);
-        self.authentication.insert(&did, &authentication_list);
-        self.updated.insert(&did, &env::block_timestamp());
+        self.set_pk_auth(&did, &mut record, &pk);
+        record.updated = env::block_timestamp();
+        self.save_record(&did, record, "set_auth_key", None);
+        self.charge_storage(&did, initial_storage);
 
         let log_message = format!("set_auth_key, did:{}, public key: {:?}", &did, pk);
         env::log(log_message.as_bytes());
@@ -298,24 +495,17 @@ impl DID {
     /// log information: "deactivate_auth_key, did: did:near:abcde.testnet, public key: "
     ///
     pub fn deactivate_auth_key(&mut self, pk: Vec<u8>) {
-        let account_id = env::signer_account_id();
-        let account_pk = env::signer_account_pk();
-        let did = gen_did(&account_id);
+        self.check_not_paused("deactivate_auth_key", PauseGroup::Keys);
+        let initial_storage = env::storage_usage();
+        let did = self.caller_did();
 
-        self.check_did_status(&did);
-        let mut public_key_list = self.public_key.get(&did).unwrap();
-        public_key_list.check_pk_access(&account_pk);
+        let mut record = self.get_valid_record(&did);
+        self.check_access(&did, &record);
 
-        let index = public_key_list.remove_pk_auth(&pk);
-        self.public_key.insert(&did, &public_key_list);
-        let mut authentication_list = self.authentication.get(&did).unwrap();
-        let i = authentication_list
-            .iter()
-            .position(|x| x == &(index as u32))
-            .unwrap();
-        authentication_list.remove(i);
-        self.authentication.insert(&did, &authentication_list);
-        self.updated.insert(&did, &env::block_timestamp());
+        self.remove_pk_auth(&did, &mut record, &pk);
+        record.updated = env::block_timestamp();
+        self.save_record(&did, record, "deactivate_auth_key", None);
+        self.charge_storage(&did, initial_storage);
 
         let log_message = format!("deactivate_auth_key, did:{}, public key: {:?}", &did, pk);
         env::log(log_message.as_bytes());
@@ -330,32 +520,38 @@ impl DID {
     /// log information: "add_new_auth_key_by_controller, did: did:near:abcde.testnet, public key: ,controller: did:near:abcdefg.testnet"
     ///
     pub fn add_new_auth_key_by_controller(&mut self, did: String, pk: Vec<u8>, controller: String) {
-        let account_id = env::signer_account_id();
-        let account_pk = env::signer_account_pk();
-        let controller_did = gen_did(&account_id);
-
-        self.check_did_status(&did);
-        self.check_did_status(&controller_did);
-        check_did(&controller);
-        let controller_list = self.controller.get(&did).unwrap();
-        if !controller_list.contains(&controller_did) {
+        self.check_not_paused("add_new_auth_key_by_controller", PauseGroup::Controllers);
+        let initial_storage = env::storage_usage();
+        let controller_did = self.caller_did();
+
+        let mut record = self.get_valid_record(&did);
+        let controller_record = self.get_valid_record(&controller_did);
+        self.config.check_did(&controller);
+        if !record.controllers.contains(&controller_did) {
             env::panic(b"add_new_auth_key_by_controller, signer is not controller")
         }
-        let controller_public_key_list = self.public_key.get(&controller_did).unwrap();
-        controller_public_key_list.check_pk_access(&account_pk);
+        self.check_access(&controller_did, &controller_record);
+        self.check_auth_key_allowed("add_new_auth_key_by_controller", &record);
 
-        let mut public_key_list = self.public_key.get(&did).unwrap();
-        if public_key_list.pk_exist(&pk) {
+        if self.find_key(&did, &pk).is_some() {
             env::panic(b"add_new_auth_key_by_controller, pk exists")
         }
+        self.check_key_limits("add_new_auth_key_by_controller", &record, &pk, &controller);
 
-        public_key_list.push(PublicKey::new_auth(&controller, pk.clone()));
-        self.public_key.insert(&did, &public_key_list);
-        let mut authentication_list = self.authentication.get(&did).unwrap();
-        let index: u32 = public_key_list.len() - 1;
-        authentication_list.push(index);
-        self.authentication.insert(&did, &authentication_list);
-        self.updated.insert(&did, &env::block_timestamp());
+        let index = self.push_key(
+            &did,
+            &mut record,
+            PublicKey::new_auth(&controller, pk.clone()),
+        );
+        record.authentication.push(index);
+        record.updated = env::block_timestamp();
+        self.save_record(
+            &did,
+            record,
+            "add_new_auth_key_by_controller",
+            Some(controller_did.clone()),
+        );
+        self.charge_storage(&did, initial_storage);
 
         let log_message = format!(
             "add_new_auth_key_by_controller, did:{}, public key: {:?}, controller: {}",
@@ -373,26 +569,27 @@ impl DID {
     /// log information: "set_auth_key_by_controller, did: did:near:abcde.testnet, public key: "
     ///
     pub fn set_auth_key_by_controller(&mut self, did: String, pk: Vec<u8>) {
-        let account_id = env::signer_account_id();
-        let account_pk = env::signer_account_pk();
-        let controller_did = gen_did(&account_id);
+        self.check_not_paused("set_auth_key_by_controller", PauseGroup::Controllers);
+        let initial_storage = env::storage_usage();
+        let controller_did = self.caller_did();
 
-        self.check_did_status(&did);
-        self.check_did_status(&controller_did);
-        let controller_list = self.controller.get(&did).unwrap();
-        if !controller_list.contains(&controller_did) {
+        let mut record = self.get_valid_record(&did);
+        let controller_record = self.get_valid_record(&controller_did);
+        if !record.controllers.contains(&controller_did) {
             env::panic(b"set_auth_key_by_controller, signer is not controller")
         }
-        let controller_public_key_list = self.public_key.get(&controller_did).unwrap();
-        controller_public_key_list.check_pk_access(&account_pk);
-
-        let mut public_key_list = self.public_key.get(&did).unwrap();
-        let index = public_key_list.set_pk_auth(&pk);
-        self.public_key.insert(&did, &public_key_list);
-        let mut authentication_list = self.authentication.get(&did).unwrap();
-        authentication_list.push(index as u32);
-        self.authentication.insert(&did, &authentication_list);
-        self.updated.insert(&did, &env::block_timestamp());
+        self.check_access(&controller_did, &controller_record);
+        self.check_auth_key_allowed("set_auth_key_by_controller", &record);
+
+        self.set_pk_auth(&did, &mut record, &pk);
+        record.updated = env::block_timestamp();
+        self.save_record(
+            &did,
+            record,
+            "set_auth_key_by_controller",
+            Some(controller_did.clone()),
+        );
+        self.charge_storage(&did, initial_storage);
 
         let log_message = format!(
             "set_auth_key_by_controller, did:{}, public key: {:?}",
@@ -410,30 +607,26 @@ impl DID {
     /// log information: "deactivate_auth_key_by_controller, did: did:near:abcde.testnet, public key: "
     ///
     pub fn deactivate_auth_key_by_controller(&mut self, did: String, pk: Vec<u8>) {
-        let account_id = env::signer_account_id();
-        let account_pk = env::signer_account_pk();
-        let controller_did = gen_did(&account_id);
+        self.check_not_paused("deactivate_auth_key_by_controller", PauseGroup::Controllers);
+        let initial_storage = env::storage_usage();
+        let controller_did = self.caller_did();
 
-        self.check_did_status(&did);
-        self.check_did_status(&controller_did);
-        let controller_list = self.controller.get(&did).unwrap();
-        if !controller_list.contains(&controller_did) {
+        let mut record = self.get_valid_record(&did);
+        let controller_record = self.get_valid_record(&controller_did);
+        if !record.controllers.contains(&controller_did) {
             env::panic(b"deactivate_auth_key_by_controller, signer is not controller")
         }
-        let controller_public_key_list = self.public_key.get(&controller_did).unwrap();
-        controller_public_key_list.check_pk_access(&account_pk);
-
-        let mut public_key_list = self.public_key.get(&did).unwrap();
-        let index = public_key_list.remove_pk_auth(&pk);
-        self.public_key.insert(&did, &public_key_list);
-        let mut authentication_list = self.authentication.get(&did).unwrap();
-        let i = authentication_list
-            .iter()
-            .position(|x| x == &(index as u32))
-            .unwrap();
-        authentication_list.remove(i);
-        self.authentication.insert(&did, &authentication_list);
-        self.updated.insert(&did, &env::block_timestamp());
+        self.check_access(&controller_did, &controller_record);
+
+        self.remove_pk_auth(&did, &mut record, &pk);
+        record.updated = env::block_timestamp();
+        self.save_record(
+            &did,
+            record,
+            "deactivate_auth_key_by_controller",
+            Some(controller_did.clone()),
+        );
+        self.charge_storage(&did, initial_storage);
 
         let log_message = format!(
             "deactivate_auth_key_by_controller, did:{}, public key: {:?}",
@@ -446,31 +639,60 @@ impl DID {
     ///
     /// this method will add service to the DID.
     ///
+    /// `service_type` is a string or an array of strings.
+    ///
+    /// `endpoint` is a URI, a map or an ordered set of them.
+    ///
+    /// `properties` are the extension properties of the service, such as "routingKeys" and "accept".
+    ///
     /// log information: "add_service, did: did:near:abcde.testnet, service id: 1234"
     ///
-    pub fn add_service(&mut self, service_id: String, service_type: String, endpoint: String) {
-        let account_id = env::signer_account_id();
-        let account_pk = env::signer_account_pk();
-        let did = gen_did(&account_id);
+    pub fn add_service(
+        &mut self,
+        service_id: String,
+        service_type: ServiceType,
+        endpoint: ServiceEndpoint,
+        properties: Option<JsonMap>,
+    ) {
+        self.check_not_paused("add_service", PauseGroup::Services);
+        let initial_storage = env::storage_usage();
+        let did = self.caller_did();
 
-        self.check_did_status(&did);
-        let public_key_list = self.public_key.get(&did).unwrap();
-        public_key_list.check_pk_access(&account_pk);
+        let mut record = self.get_valid_record(&did);
+        self.check_access(&did, &record);
 
         let ser = Service {
             id: service_id,
             tp: service_type,
             service_endpoint: endpoint,
+            properties: properties.unwrap_or_default(),
         };
-        let mut sers = self.service.get(&did).unwrap_or(vec![]);
-        let index = sers.iter().position(|x| &x.id == &ser.id);
+        assert_valid("add_service", check_service(&ser, &self.config));
+        let index = record.service_index(&ser.id);
         let log_message = format!("add_service, did:{}, service id: {}", &did, &ser.id);
         if index.is_some() {
             env::panic(b"add_service, service exists")
         }
-        sers.push(ser);
-        self.service.insert(&did, &sers);
-        self.updated.insert(&did, &env::block_timestamp());
+        let limits = &self.config.limits;
+        assert_valid(
+            "add_service",
+            check_count("services", record.services.len() + 1, limits.max_services),
+        );
+        assert_valid(
+            "add_service",
+            check_length(
+                "service",
+                service_json_length(&ser),
+                limits.max_service_length,
+            ),
+        );
+        let version = record.version + 1;
+        self.service
+            .insert(&service_key(&did, &ser.id, version), &ser);
+        record.services.push((ser.id, version));
+        record.updated = env::block_timestamp();
+        self.save_record(&did, record, "add_service", None);
+        self.charge_storage(&did, initial_storage);
         env::log(log_message.as_bytes());
     }
 
@@ -478,36 +700,58 @@ impl DID {
     ///
     /// this method will update service.
     ///
+    /// if `properties` is None, the extension properties of the service are kept.
+    ///
     /// log information: "update_service, did: did:near:abcde.testnet, service id: 1234"
     ///
-    pub fn update_service(&mut self, service_id: String, service_type: String, endpoint: String) {
-        let account_id = env::signer_account_id();
-        let account_pk = env::signer_account_pk();
-        let did = gen_did(&account_id);
-
-        self.check_did_status(&did);
-        let public_key_list = self.public_key.get(&did).unwrap();
-        public_key_list.check_pk_access(&account_pk);
-
-        let ser = Service {
-            id: service_id,
-            tp: service_type,
-            service_endpoint: endpoint,
-        };
-        let mut sers = self.service.get(&did).unwrap_or(vec![]);
-        let index = sers.iter().position(|x| &x.id == &ser.id);
-        let log_message = format!("update_service, did:{}, service id: {}", &did, &ser.id);
+    pub fn update_service(
+        &mut self,
+        service_id: String,
+        service_type: ServiceType,
+        endpoint: ServiceEndpoint,
+        properties: Option<JsonMap>,
+    ) {
+        self.check_not_paused("update_service", PauseGroup::Services);
+        let initial_storage = env::storage_usage();
+        let did = self.caller_did();
+
+        let mut record = self.get_valid_record(&did);
+        self.check_access(&did, &record);
+
+        let index = record.service_index(&service_id);
+        let log_message = format!("update_service, did:{}, service id: {}", &did, &service_id);
         match index {
             Some(ind) => {
-                let res = sers.get_mut(ind).unwrap();
-                res.id = ser.id;
-                res.tp = ser.tp;
-                res.service_endpoint = ser.service_endpoint;
-                self.service.insert(&did, &sers);
+                let written = record.services[ind].1;
+                let mut res = self
+                    .service
+                    .get(&service_key(&did, &service_id, written))
+                    .unwrap();
+                res.tp = service_type;
+                res.service_endpoint = endpoint;
+                if let Some(props) = properties {
+                    res.properties = props;
+                }
+                assert_valid("update_service", check_service(&res, &self.config));
+                assert_valid(
+                    "update_service",
+                    check_length(
+                        "service",
+                        service_json_length(&res),
+                        self.config.limits.max_service_length,
+                    ),
+                );
+                // the service written by the previous version is kept for the history
+                let version = record.version + 1;
+                self.service
+                    .insert(&service_key(&did, &service_id, version), &res);
+                record.services[ind].1 = version;
             }
             _ => env::panic(b"update_service, service doesn't exist"),
         }
-        self.updated.insert(&did, &env::block_timestamp());
+        record.updated = env::block_timestamp();
+        self.save_record(&did, record, "update_service", None);
+        self.charge_storage(&did, initial_storage);
         env::log(log_message.as_bytes());
     }
 
@@ -518,25 +762,24 @@ impl DID {
     /// log information: "remove_service, did: did:near:abcde.testnet, service id: 1234"
     ///
     pub fn remove_service(&mut self, service_id: String) {
-        let account_id = env::signer_account_id();
-        let account_pk = env::signer_account_pk();
-        let did = gen_did(&account_id);
+        self.check_not_paused("remove_service", PauseGroup::Services);
+        let initial_storage = env::storage_usage();
+        let did = self.caller_did();
 
-        self.check_did_status(&did);
-        let public_key_list = self.public_key.get(&did).unwrap();
-        public_key_list.check_pk_access(&account_pk);
+        let mut record = self.get_valid_record(&did);
+        self.check_access(&did, &record);
 
-        let mut sers = self.service.get(&did).unwrap_or(vec![]);
-        let index = sers.iter().position(|x| &x.id == &service_id);
+        let index = record.service_index(&service_id);
         let log_message = format!("remove_service, did:{}, service id: {}", &did, &service_id);
         match index {
             Some(ind) => {
-                sers.remove(ind);
-                self.service.insert(&did, &sers);
+                record.services.remove(ind);
             }
             _ => env::panic(b"remove_service, service doesn't exist"),
         }
-        self.updated.insert(&did, &env::block_timestamp());
+        record.updated = env::block_timestamp();
+        self.save_record(&did, record, "remove_service", None);
+        self.charge_storage(&did, initial_storage);
         env::log(log_message.as_bytes());
     }
 
@@ -544,69 +787,91 @@ impl DID {
     ///
     /// this method will add context.
     ///
+    /// every context must be an http(s) URI, and in the allowlist if it's set. the contexts are kept in the order they are added,
+    /// the ones which already exist are ignored.
+    ///
     /// log information: "add_context, did: did:near:abcde.testnet, context: 1234"
     ///
     pub fn add_context(&mut self, context: Vec<String>) {
-        let account_id = env::signer_account_id();
-        let account_pk = env::signer_account_pk();
-        let did = gen_did(&account_id);
+        self.check_not_paused("add_context", PauseGroup::Contexts);
+        let initial_storage = env::storage_usage();
+        let did = self.caller_did();
 
-        self.check_did_status(&did);
-        let public_key_list = self.public_key.get(&did).unwrap();
-        public_key_list.check_pk_access(&account_pk);
+        let mut record = self.get_valid_record(&did);
+        self.check_access(&did, &record);
 
         let log_message = format!("add_context, did:{}, context: {:?}", &did, &context);
-        let mut cons = self.contexts.get(&did).unwrap_or(vec![]);
-        for v in context.iter() {
-            if !cons.contains(v) && v != DEFAULT_CONTEXT1 && v != DEFAULT_CONTEXT2 {
-                cons.push(v.clone());
-            };
-        }
-        self.contexts.insert(&did, &cons);
-        self.updated.insert(&did, &env::block_timestamp());
+        record.contexts = self.check_contexts("add_context", record.contexts.clone(), context);
+        record.updated = env::block_timestamp();
+        self.save_record(&did, record, "add_context", None);
+        self.charge_storage(&did, initial_storage);
         env::log(log_message.as_bytes());
     }
 
     /// remove_context
     ///
-    /// this method will remove context.
+    /// this method will remove context, the order of the other contexts is kept.
     ///
     /// log information: "remove_context, did: did:near:abcde.testnet, context: 1234"
     ///
     pub fn remove_context(&mut self, context: Vec<String>) {
-        let account_id = env::signer_account_id();
-        let account_pk = env::signer_account_pk();
-        let did = gen_did(&account_id);
+        self.check_not_paused("remove_context", PauseGroup::Contexts);
+        let initial_storage = env::storage_usage();
+        let did = self.caller_did();
 
-        self.check_did_status(&did);
-        let public_key_list = self.public_key.get(&did).unwrap();
-        public_key_list.check_pk_access(&account_pk);
+        let mut record = self.get_valid_record(&did);
+        self.check_access(&did, &record);
 
-        let mut cons = self.contexts.get(&did).unwrap_or(vec![]);
         for v in context.iter() {
-            let index = cons.iter().position(|x| x == v);
-            if let Some(ind) = index {
-                cons.remove(ind);
+            match record.contexts.iter().position(|x| x == v) {
+                Some(ind) => {
+                    record.contexts.remove(ind);
+                }
+                None => env::panic(b"remove_context, context doesn't exist"),
             }
         }
 
-        self.updated.insert(&did, &env::block_timestamp());
+        record.updated = env::block_timestamp();
+        self.save_record(&did, record, "remove_context", None);
+        self.charge_storage(&did, initial_storage);
         let log_message = format!("remove_context, did: {}, context: {:?}", &did, &context);
         env::log(log_message.as_bytes());
     }
 
+    /// set_contexts
+    ///
+    /// this method will replace the contexts of the DID with `context`, in the given order.
+    /// the default contexts are always kept in front of them.
+    ///
+    /// log information: "set_contexts, did: did:near:abcde.testnet, context: ["https://example.com/v1"]"
+    ///
+    pub fn set_contexts(&mut self, context: Vec<String>) {
+        self.check_not_paused("set_contexts", PauseGroup::Contexts);
+        let initial_storage = env::storage_usage();
+        let did = self.caller_did();
+
+        let mut record = self.get_valid_record(&did);
+        self.check_access(&did, &record);
+
+        let log_message = format!("set_contexts, did: {}, context: {:?}", &did, &context);
+        record.contexts = self.check_contexts("set_contexts", vec![], context);
+        record.updated = env::block_timestamp();
+        self.save_record(&did, record, "set_contexts", None);
+        self.charge_storage(&did, initial_storage);
+        env::log(log_message.as_bytes());
+    }
+
     /// verify_signature
     ///
     /// this method will verify a transaction is signed by did athentication key.
     ///
+    /// the DID authorized by predecessor is verified by the predecessor account instead.
+    ///
     pub fn verify_signature(&self) {
-        let account_id = env::signer_account_id();
-        let account_pk = env::signer_account_pk();
-        let did = gen_did(&account_id);
+        let did = self.caller_did();
 
-        self.check_did_status(&did);
-        let public_key_list = self.public_key.get(&did).unwrap();
-        public_key_list.check_pk_access(&account_pk);
+        let record = self.get_valid_record(&did);
+        self.check_access(&did, &record);
     }
 
     /// verify_controller
@@ -616,18 +881,79 @@ impl DID {
     /// this method will verify a DID is or not the controller.
     ///
     pub fn verify_controller(&self, did: String) {
-        let account_id = env::signer_account_id();
-        let account_pk = env::signer_account_pk();
-        let controller_did = gen_did(&account_id);
+        let controller_did = self.caller_did();
 
-        self.check_did_status(&did);
-        self.check_did_status(&controller_did);
-        let controller_list = self.controller.get(&did).unwrap();
-        if !controller_list.contains(&controller_did) {
+        let record = self.get_valid_record(&did);
+        let controller_record = self.get_valid_record(&controller_did);
+        if !record.controllers.contains(&controller_did) {
             env::panic(b"verify_controller, signer is not controller")
         }
-        let controller_public_key_list = self.public_key.get(&controller_did).unwrap();
-        controller_public_key_list.check_pk_access(&account_pk);
+        self.check_access(&controller_did, &controller_record);
+    }
+
+    /// is_authentication_key
+    ///
+    /// this method query whether `pk` is a valid authentication key of the DID, it returns false instead of panicking,
+    /// so that other contracts can branch on the result in a callback.
+    ///
+    pub fn is_authentication_key(&self, did: String, pk: Vec<u8>) -> bool {
+        match self.record.get(&did) {
+            Some(record) if record.is_valid() => (),
+            _ => return false,
+        }
+        match self.find_key(&did, &pk) {
+            Some((_, public_key)) => public_key.has_relationship(RELATIONSHIP_AUTHENTICATION),
+            None => false,
+        }
+    }
+
+    /// is_controller
+    ///
+    /// this method query whether `controller` is a controller of the DID, and both of them are valid. it returns false instead of panicking.
+    ///
+    pub fn is_controller(&self, did: String, controller: String) -> bool {
+        let record = match self.record.get(&did) {
+            Some(v) if v.is_valid() => v,
+            _ => return false,
+        };
+        record.controllers.contains(&controller)
+            && self
+                .record
+                .get(&controller)
+                .map(|x| x.is_valid())
+                .unwrap_or(false)
+    }
+
+    /// has_relationship
+    ///
+    /// this method query whether the key `key_id`, such as "did:near:abcde.testnet#keys-1", is valid and used for the relationship of the DID.
+    ///
+    /// `relationship` is "publicKey" or "authentication", false is returned for the other relationships.
+    ///
+    pub fn has_relationship(&self, did: String, key_id: String, relationship: String) -> bool {
+        if relationship != RELATIONSHIP_PUBLIC_KEY && relationship != RELATIONSHIP_AUTHENTICATION {
+            return false;
+        }
+        let record = match self.record.get(&did) {
+            Some(v) if v.is_valid() => v,
+            _ => return false,
+        };
+        match parse_key_id(&did, &key_id) {
+            Some(index) if index < record.key_count => self
+                .public_key
+                .get(&key_id)
+                .map(|x| x.has_relationship(&relationship))
+                .unwrap_or(false),
+            _ => false,
+        }
+    }
+
+    /// get_auth_mode
+    ///
+    /// this method query how the owner of the DID is authorized, "signer" or "predecessor".
+    ///
+    pub fn get_auth_mode(&self, did: String) -> Option<AuthMode> {
+        self.record.get(&did).map(|x| x.auth_mode)
     }
 
     /// get_document
@@ -637,37 +963,576 @@ impl DID {
     /// this method query the DID information.
     ///
     pub fn get_document(&self, did: String) -> Option<String> {
-        let public_key_list = self.public_key.get(&did)?;
-        let pk_list_json = public_key_list.get_pk_json(&did);
-        let auth_index_list = self.authentication.get(&did)?;
-        let authentication_list_json =
-            public_key_list.get_authentication_json(&did, auth_index_list);
-        let mut cts = self.contexts.get(&did).unwrap_or(vec![]);
+        let record = self.record.get(&did)?;
+        self.render_document(did, &record)
+    }
+
+    /// get_version_id
+    ///
+    /// this method query the current versionId of the DID, every update of the DID increases it by one.
+    ///
+    /// return 0 if the DID is not registered.
+    ///
+    pub fn get_version_id(&self, did: String) -> u64 {
+        self.record.get(&did).map(|x| x.version).unwrap_or(0)
+    }
+
+    /// get_document_at
+    ///
+    /// this method query the did document of the given versionId, return json string.
+    ///
+    /// return None if the version doesn't exist or the DID was deactivated in that version.
+    ///
+    /// every version is kept with its keys and services, its storage is paid by the DID when it's written.
+    ///
+    pub fn get_document_at(&self, did: String, version_id: u64) -> Option<String> {
+        let record = self.version_record.get(&version_key(&did, version_id))?;
+        self.render_document(did, &record)
+    }
+
+    /// get_document_at_time
+    ///
+    /// this method query the did document which was valid at the given time, return json string.
+    ///
+    /// `timestamp` is the block timestamp in nanoseconds.
+    ///
+    pub fn get_document_at_time(&self, did: String, timestamp: u64) -> Option<String> {
+        // the versionId at `timestamp` is the number of operations done before it
+        let (mut low, mut high) = (0, self.get_version_id(did.clone()));
+        while low < high {
+            let mid = low + (high - low) / 2;
+            let operation = self.operation.get(&operation_key(&did, mid)).unwrap();
+            if operation.timestamp <= timestamp {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        if low == 0 {
+            return None;
+        }
+        self.get_document_at(did, low)
+    }
+
+    /// get_operations
+    ///
+    /// this method query the operation log of the DID, at most `limit` operations starting from `from_index`.
+    ///
+    /// every operation records the method name, signer account, signer public key, the controller if it's done by controller,
+    /// block height, timestamp and the versionId it created.
+    ///
+    pub fn get_operations(&self, did: String, from_index: u64, limit: u64) -> Vec<Operation> {
+        let count = self.get_version_id(did.clone());
+        (from_index..std::cmp::min(from_index.saturating_add(limit), count))
+            .filter_map(|i| self.operation.get(&operation_key(&did, i)))
+            .collect()
+    }
+
+    /// get_did_count
+    ///
+    /// this method query the number of registered DIDs, including the deactivated ones
+    /// and the slots of the DIDs removed because their account couldn't be created.
+    ///
+    pub fn get_did_count(&self) -> u64 {
+        self.dids.len()
+    }
+
+    /// get_dids
+    ///
+    /// this method query the registered DIDs among at most `limit` slots starting from `from_index`, in registration order.
+    /// the slots of the DIDs removed because their account couldn't be created are skipped.
+    ///
+    pub fn get_dids(&self, from_index: u64, limit: u64) -> Vec<String> {
+        (from_index..std::cmp::min(from_index.saturating_add(limit), self.dids.len()))
+            .filter_map(|i| self.registered_did(i))
+            .collect()
+    }
+
+    /// get_dids_by_status
+    ///
+    /// this method query the DIDs which are active (`active` is true) or deactivated (`active` is false) among at most `limit`
+    /// registered DIDs starting from `from_index`, in registration order.
+    ///
+    /// `nextIndex` of the result is the `from_index` of the next page, it's null after the last DID.
+    ///
+    pub fn get_dids_by_status(&self, active: bool, from_index: u64, limit: u64) -> DidPage {
+        let end = std::cmp::min(from_index.saturating_add(limit), self.dids.len());
+        let dids = (from_index..end)
+            .filter_map(|i| self.registered_did(i))
+            .filter(|did| match self.record.get(did) {
+                Some(record) => record.is_valid() == active,
+                None => false,
+            })
+            .collect();
+        DidPage {
+            dids,
+            next_index: if end < self.dids.len() {
+                Some(end)
+            } else {
+                None
+            },
+        }
+    }
+
+    /// get_dids_registered_after
+    ///
+    /// this method query the DIDs registered after `timestamp` among at most `limit` slots, skipping the first `from_index` slots.
+    ///
+    /// `timestamp` is the block timestamp in nanoseconds. the DIDs are stored in registration order, the first one
+    /// registered after `timestamp` is found by binary search.
+    ///
+    pub fn get_dids_registered_after(
+        &self,
+        timestamp: u64,
+        from_index: u64,
+        limit: u64,
+    ) -> Vec<String> {
+        let (mut low, mut high) = (0, self.dids.len());
+        while low < high {
+            let mid = low + (high - low) / 2;
+            // a removed DID has no time, it's ordered with the next registered one
+            let created = (mid..self.dids.len())
+                .find_map(|i| self.registered_did(i))
+                .and_then(|did| self.record.get(&did))
+                .map(|record| record.created);
+            if created.map(|x| x > timestamp).unwrap_or(true) {
+                high = mid;
+            } else {
+                low = mid + 1;
+            }
+        }
+        self.get_dids(low.saturating_add(from_index), limit)
+    }
+
+    /// find_dids_by_key
+    ///
+    /// this method query the DIDs which reference the public key, and the id of the key in each DID document.
+    ///
+    /// `relationship` is "publicKey" or "authentication", if it's given only the DIDs which use the key for this relationship are returned.
+    /// no DID is returned for another relationship, as `has_relationship` returns false for it.
+    ///
+    /// deactivated keys are not returned.
+    ///
+    pub fn find_dids_by_key(&self, pk: Vec<u8>, relationship: Option<String>) -> Vec<KeyReference> {
+        let mut result = vec![];
+        if let Some(rel) = &relationship {
+            if rel != RELATIONSHIP_PUBLIC_KEY && rel != RELATIONSHIP_AUTHENTICATION {
+                return result;
+            }
+        }
+        for (did, index) in self.key_index.get(&pk).unwrap_or(vec![]) {
+            let key_id = key_id(&did, index);
+            let public_key = match self.public_key.get(&key_id) {
+                Some(v) => v,
+                None => continue,
+            };
+            let matched = match &relationship {
+                Some(rel) => public_key.has_relationship(rel),
+                None => !public_key.is_deactivated(),
+            };
+            if matched {
+                result.push(KeyReference { did, key_id });
+            }
+        }
+        result
+    }
+
+    /// get_controlled_dids
+    ///
+    /// this method query at most `limit` DIDs controlled by `controller`, starting from `from_index`.
+    ///
+    pub fn get_controlled_dids(
+        &self,
+        controller: String,
+        from_index: u64,
+        limit: u64,
+    ) -> Vec<String> {
+        self.controlled
+            .get(&controller)
+            .unwrap_or(vec![])
+            .into_iter()
+            .skip(from_index as usize)
+            .take(limit as usize)
+            .collect()
+    }
+
+    /// set_endpoint_schemes
+    ///
+    /// this method will set the schemes allowed in service endpoint URIs. only the owner and the admins have the right to invoke this method.
+    ///
+    /// log information: "set_endpoint_schemes, schemes: ["https"]"
+    ///
+    pub fn set_endpoint_schemes(&mut self, schemes: Vec<String>) {
+        self.check_admin();
+        let schemes: Vec<String> = schemes.iter().map(|x| x.to_ascii_lowercase()).collect();
+        let log_message = format!("set_endpoint_schemes, schemes: {:?}", &schemes);
+        self.config.endpoint_schemes = schemes;
+        env::log(log_message.as_bytes());
+    }
+
+    /// set_service_types
+    ///
+    /// this method will set the registry of service types, any type is allowed if the registry is empty.
+    /// only the owner and the admins have the right to invoke this method.
+    ///
+    /// log information: "set_service_types, service types: ["LinkedDomains"]"
+    ///
+    pub fn set_service_types(&mut self, service_types: Vec<String>) {
+        self.check_admin();
+        let log_message = format!("set_service_types, service types: {:?}", &service_types);
+        self.config.service_types = service_types;
+        env::log(log_message.as_bytes());
+    }
+
+    /// set_context_allowlist
+    ///
+    /// this method will set the allowed JSON-LD contexts, any context is allowed if it's empty.
+    /// only the owner and the admins have the right to invoke this method.
+    ///
+    /// the allowlist only applies to later updates, the contexts already added are kept.
+    ///
+    /// log information: "set_context_allowlist, contexts: ["https://example.com/v1"]"
+    ///
+    pub fn set_context_allowlist(&mut self, contexts: Vec<String>) {
+        self.check_admin();
+        for v in contexts.iter() {
+            assert_valid("set_context_allowlist", check_context(v, &[]));
+        }
+        let log_message = format!("set_context_allowlist, contexts: {:?}", &contexts);
+        self.config.context_allowlist = contexts;
+        env::log(log_message.as_bytes());
+    }
+
+    /// set_limits
+    ///
+    /// this method will set the per-DID quotas and field length limits. only the owner and the admins have the right to invoke this method.
+    ///
+    /// the limits only apply to later updates, DIDs which already exceed them are kept.
+    ///
+    /// log information: "set_limits, limits: Limits { max_public_keys: 32, .. }"
+    ///
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.check_admin();
+        let log_message = format!("set_limits, limits: {:?}", &limits);
+        self.config.limits = limits;
+        env::log(log_message.as_bytes());
+    }
+
+    /// get_limits
+    ///
+    /// this method query the per-DID quotas and field length limits.
+    ///
+    pub fn get_limits(&self) -> Limits {
+        self.config.limits.clone()
+    }
+
+    /// get_config
+    ///
+    /// this method query the contract-level configuration.
+    ///
+    pub fn get_config(&self) -> Config {
+        self.config.clone()
+    }
+
+    fn index_controller(&mut self, controller: &String, did: &String) {
+        let mut dids = self.controlled.get(controller).unwrap_or(vec![]);
+        if !dids.contains(did) {
+            dids.push(did.clone());
+            self.controlled.insert(controller, &dids);
+        }
+    }
+
+    fn unindex_controller(&mut self, controller: &String, did: &String) {
+        let mut dids = self.controlled.get(controller).unwrap_or(vec![]);
+        dids.retain(|x| x != did);
+        if dids.is_empty() {
+            self.controlled.remove(controller);
+        } else {
+            self.controlled.insert(controller, &dids);
+        }
+    }
+
+    /// `index` is the position of the key in the DID, starting from 0.
+    fn index_key(&mut self, pk: &Vec<u8>, did: &String, index: u32) {
+        let mut entries = self.key_index.get(pk).unwrap_or(vec![]);
+        if !entries.contains(&(did.clone(), index)) {
+            entries.retain(|(x, _)| x != did);
+            entries.push((did.clone(), index));
+            self.key_index.insert(pk, &entries);
+        }
+    }
+
+    fn unindex_key(&mut self, pk: &Vec<u8>, did: &String) {
+        let mut entries = self.key_index.get(pk).unwrap_or(vec![]);
+        entries.retain(|(x, _)| x != did);
+        if entries.is_empty() {
+            self.key_index.remove(pk);
+        } else {
+            self.key_index.insert(pk, &entries);
+        }
+    }
+
+    /// find the public key of the DID through the key lookup of the DID, return its position and the key.
+    fn find_key(&self, did: &String, pk: &Vec<u8>) -> Option<(u32, PublicKey)> {
+        let index = self.key_lookup.get(&key_lookup_key(did, pk))?;
+        let public_key = self.public_key.get(&key_id(did, index))?;
+        Some((index, public_key))
+    }
+
+    /// store the public key as the last key of the DID, return its position.
+    fn push_key(&mut self, did: &String, record: &mut DidRecord, public_key: PublicKey) -> u32 {
+        let index = record.key_count;
+        self.index_key(public_key.public_key(), did, index);
+        self.key_lookup
+            .insert(&key_lookup_key(did, public_key.public_key()), &index);
+        self.public_key.insert(&key_id(did, index), &public_key);
+        record.key_count += 1;
+        index
+    }
+
+    fn set_pk_auth(&mut self, did: &String, record: &mut DidRecord, pk: &Vec<u8>) {
+        let (index, mut public_key) = match self.find_key(did, pk) {
+            Some(v) => v,
+            None => env::panic(b"set_pk_auth, pk doesn't exist"),
+        };
+        public_key.set_auth();
+        self.public_key.insert(&key_id(did, index), &public_key);
+        record.authentication.push(index);
+    }
+
+    fn remove_pk_auth(&mut self, did: &String, record: &mut DidRecord, pk: &Vec<u8>) {
+        let (index, mut public_key) = match self.find_key(did, pk) {
+            Some(v) => v,
+            None => env::panic(b"remove_pk_auth, pk doesn't exist"),
+        };
+        public_key.remove_auth();
+        self.public_key.insert(&key_id(did, index), &public_key);
+        let i = record
+            .authentication
+            .iter()
+            .position(|x| x == &index)
+            .unwrap();
+        record.authentication.remove(i);
+    }
+
+    /// check the invariants of the record, increase the versionId of the DID, then store the record, its copy for the new version and the operation.
+    ///
+    /// the copy and the operation are never removed, so every update grows the storage of the DID by a full record and an operation,
+    /// a few hundred bytes, which are charged to its storage balance and never refunded.
+    fn save_record(
+        &mut self,
+        did: &String,
+        mut record: DidRecord,
+        kind: &str,
+        controller: Option<String>,
+    ) {
+        let violations = record_violations(&record);
+        if !violations.is_empty() {
+            env::panic(
+                format!("{}, integrity violation: {}", kind, violations.join("; ")).as_bytes(),
+            )
+        }
+        record.version += 1;
+        let operation = Operation {
+            kind: kind.to_string(),
+            signer_account: env::signer_account_id(),
+            signer_key: env::signer_account_pk().to_base58(),
+            predecessor_account: env::predecessor_account_id(),
+            controller,
+            block_height: env::block_index(),
+            timestamp: env::block_timestamp(),
+            version_id: record.version,
+        };
+        self.operation
+            .insert(&operation_key(did, record.version - 1), &operation);
+        self.version_record
+            .insert(&version_key(did, record.version), &record);
+        self.record.insert(did, &record);
+    }
+
+    fn render_document(&self, did: String, record: &DidRecord) -> Option<String> {
+        let document = self.build_document(did, record)?;
+        let document_json = serde_json::to_string(&document).unwrap_or("".to_string());
+        Some(document_json)
+    }
+
+    /// the document of the DID, None if it's deactivated.
+    fn build_document(&self, did: String, record: &DidRecord) -> Option<Document> {
+        if !record.is_valid() {
+            return None;
+        }
+        let mut pk_list_json = vec![];
+        for i in 0..record.key_count {
+            let public_key = self.public_key.get(&key_id(&did, i)).unwrap();
+            if public_key.is_pk_list() {
+                pk_list_json.push(public_key.to_json(&did, i));
+            }
+        }
+        let mut authentication_list_json = vec![];
+        for i in record.authentication.iter() {
+            let public_key = self.public_key.get(&key_id(&did, *i)).unwrap();
+            if public_key.is_pk_list() {
+                authentication_list_json.push(Authentication::Pk(key_id(&did, *i)));
+            } else {
+                authentication_list_json.push(Authentication::NotPK(public_key.to_json(&did, *i)));
+            }
+        }
         let mut contexts = vec![DEFAULT_CONTEXT1.to_string(), DEFAULT_CONTEXT2.to_string()];
-        contexts.append(&mut cts);
-        let mut service = self.service.get(&did).unwrap_or(vec![]);
-        for v in service.iter_mut() {
+        contexts.extend(record.contexts.iter().cloned());
+        let mut service = vec![];
+        for (id, version) in record.services.iter() {
+            let mut v = self.service.get(&service_key(&did, id, *version)).unwrap();
             v.id = format!("{}#{}", &did, v.id);
+            service.push(v);
         }
         let document = Document {
             contexts,
             public_key: pk_list_json,
             authentication: authentication_list_json,
-            controller: self.controller.get(&did).unwrap_or(vec![]),
+            controller: record.controllers.clone(),
+            also_known_as: record.also_known_as.clone(),
             service,
-            created: self.created.get(&did).unwrap_or(0),
-            updated: self.updated.get(&did).unwrap_or(0),
+            created: record.created,
+            updated: record.updated,
             id: did,
         };
-        let document_json = serde_json::to_string(&document).unwrap_or("".to_string());
-        Some(document_json)
+        Some(document)
+    }
+
+    /// check the contexts appended to `existing` and the limits, return all of them without the duplicates and the default contexts.
+    fn check_contexts(
+        &self,
+        method: &str,
+        existing: Vec<String>,
+        contexts: Vec<String>,
+    ) -> Vec<String> {
+        let limits = &self.config.limits;
+        let mut result = existing;
+        for v in contexts {
+            assert_valid(
+                method,
+                check_length("context", v.len(), limits.max_context_length),
+            );
+            if result.contains(&v) || v == DEFAULT_CONTEXT1 || v == DEFAULT_CONTEXT2 {
+                continue;
+            }
+            assert_valid(method, check_context(&v, &self.config.context_allowlist));
+            result.push(v);
+        }
+        assert_valid(
+            method,
+            check_count("contexts", result.len(), limits.max_contexts),
+        );
+        result
+    }
+
+    fn check_key_limits(
+        &self,
+        method: &str,
+        record: &DidRecord,
+        pk: &Vec<u8>,
+        controller: &String,
+    ) {
+        let limits = &self.config.limits;
+        assert_valid(method, check_not_empty("public key", pk.len()));
+        assert_valid(
+            method,
+            check_length("public key", pk.len(), limits.max_public_key_length),
+        );
+        assert_valid(
+            method,
+            check_length("controller", controller.len(), limits.max_did_length),
+        );
+        assert_valid(
+            method,
+            check_count(
+                "public keys",
+                record.key_count as usize + 1,
+                limits.max_public_keys,
+            ),
+        );
+    }
+
+    /// the DID at `index` of `dids`, None if it was removed.
+    fn registered_did(&self, index: u64) -> Option<String> {
+        self.dids.get(index).filter(|x| x != REMOVED_DID)
+    }
+
+    /// the DID of the caller, the one of the predecessor account if it's authorized by predecessor,
+    /// otherwise the DID bound to the signer account or the one of the signer account.
+    fn caller_did(&self) -> String {
+        let did = self.config.gen_did(&env::predecessor_account_id());
+        if let Some(record) = self.record.get(&did) {
+            if record.auth_mode == AuthMode::Predecessor {
+                return did;
+            }
+        }
+        self.account_did(&env::signer_account_id())
+    }
+
+    /// the DID of the account, the key-derived DID bound to it or the DID generated from it.
+    fn account_did(&self, account_id: &String) -> String {
+        match self.bound_did.get(account_id) {
+            Some(v) => v,
+            None => self.config.gen_did(account_id),
+        }
+    }
+
+    /// the account which owns the DID of the caller.
+    fn caller_account(&self, record: &DidRecord) -> String {
+        match record.auth_mode {
+            AuthMode::Signer => env::signer_account_id(),
+            AuthMode::Predecessor => env::predecessor_account_id(),
+        }
+    }
+
+    /// panic if the DID is authorized by the predecessor account, an authentication key wouldn't authorize it.
+    fn check_auth_key_allowed(&self, method: &str, record: &DidRecord) {
+        if record.auth_mode == AuthMode::Predecessor {
+            env::panic(
+                format!(
+                    "{}, did is authorized by predecessor, it has no authentication keys",
+                    method
+                )
+                .as_bytes(),
+            )
+        }
     }
 
-    fn check_did_status(&self, did: &String) {
-        let status = self.status.get(did).unwrap();
-        match status {
-            Status::VALID => (),
-            _ => env::panic(b"did status is not valid"),
+    /// check the caller is authorized to act as the DID, by the predecessor account or by the signer key.
+    fn check_access(&self, did: &String, record: &DidRecord) {
+        match record.auth_mode {
+            AuthMode::Signer => self.check_pk_access(did, &env::signer_account_pk()),
+            AuthMode::Predecessor => {
+                if &self.config.gen_did(&env::predecessor_account_id()) != did {
+                    env::panic(b"check_access, predecessor is not the DID account")
+                }
+            }
+        }
+    }
+
+    fn check_pk_access(&self, did: &String, pk: &Vec<u8>) {
+        match self.find_key(did, pk) {
+            Some((_, public_key)) => {
+                if public_key.is_deactivated() {
+                    env::panic(b"check_pk_access, pk is deactivated")
+                }
+                if !public_key.is_authentication() {
+                    env::panic(b"check_pk_access, pk is not authentication")
+                }
+            }
+            None => env::panic(b"check_pk_access, pk doesn't exist"),
+        }
+    }
+
+    fn get_valid_record(&self, did: &String) -> DidRecord {
+        let record = match self.record.get(did) {
+            Some(v) => v,
+            None => env::panic(b"did doesn't exist"),
         };
+        if !record.is_valid() {
+            env::panic(b"did status is not valid")
+        }
+        record
     }
 }
@@ -0,0 +1,213 @@
+//! state migration
+//!
+//! The contract state is stored with Borsh, so any change of its layout makes the deployed state unreadable.
+//! The state carries its version, a new layout increases `STATE_VERSION` and `migrate` upgrades the
+//! state of the previous layouts in place after the new code is deployed.
+
+use super::*;
+use near_sdk::collections::UnorderedMap;
+
+/// the version of the current state layout.
+///
+/// layout 1 is the first versioned layout, it's released with all the fields added to it before the release:
+/// the owner and roles, the pause groups, the upgrade config and the staged code, the storage accounts, the bound DIDs, the key lookup,
+/// the context allowlist, DID prefix and limits of the config, and the auth mode, bound account and alsoKnownAs of the records.
+/// since the release, any change of the Borsh layout of the contract or of the values it stores increases it, with a migration.
+pub const STATE_VERSION: u32 = 1;
+
+const STATE_KEY: &[u8] = b"STATE";
+/// the key of the state of the first layout while its DIDs are migrated.
+const LEGACY_STATE_KEY: &[u8] = b"STATE_V0";
+
+/// the public key list of the first layout, all the keys of a DID are stored together.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct PublicKeyListV0 {
+    pub public_key_list: Vec<PublicKey>,
+}
+
+/// the service of the first layout, its type and endpoint are strings.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct ServiceV0 {
+    pub id: String,
+    pub tp: String,
+    pub service_endpoint: String,
+}
+
+/// the first state layout, it has no version and stores every DID in eight maps.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct DIDV0 {
+    pub status: UnorderedMap<String, Status>,
+    pub contexts: UnorderedMap<String, Vec<String>>,
+    pub public_key: UnorderedMap<String, PublicKeyListV0>,
+    pub authentication: UnorderedMap<String, Vec<u32>>,
+    pub controller: UnorderedMap<String, Vec<String>>,
+    pub service: UnorderedMap<String, Vec<ServiceV0>>,
+    pub created: UnorderedMap<String, u64>,
+    pub updated: UnorderedMap<String, u64>,
+}
+
+#[near_bindgen]
+impl DID {
+    /// migrate
+    ///
+    /// this method will upgrade the contract state of a previous layout to the current one.
+    /// only the contract account has the right to invoke this method, right after the new code is deployed.
+    ///
+    /// it's invoked after every deployment of a staged code, the state is kept with its owner and config if it's up to date,
+    /// and `owner_id` and `config` must be None.
+    ///
+    /// the first layout has no owner, so `owner_id` is required to migrate it, the default configuration is used if `config` is None.
+    /// its DIDs are migrated afterwards by `migrate_dids`, the mutations of DIDs panic until all of them are migrated.
+    ///
+    /// log information: "migrate, state version: 0 -> 1, dids to migrate: 10"
+    ///
+    #[init]
+    pub fn migrate(owner_id: Option<String>, config: Option<Config>) -> Self {
+        if env::predecessor_account_id() != env::current_account_id() {
+            env::panic(b"migrate, only the contract account can invoke this method")
+        }
+        let data = match env::storage_read(STATE_KEY) {
+            Some(v) => v,
+            None => env::panic(b"migrate, contract state doesn't exist"),
+        };
+        if let Ok(state) = DID::try_from_slice(&data) {
+            if state.state_version == STATE_VERSION {
+                if owner_id.is_some() || config.is_some() {
+                    env::panic(b"migrate, owner and config of an up to date state are kept")
+                }
+                // the code is upgraded without changing the layout
+                env::log(b"migrate, contract state is up to date");
+                return state;
+            }
+        }
+        let old = match DIDV0::try_from_slice(&data) {
+            Ok(v) => v,
+            Err(_) => env::panic(b"migrate, unknown contract state"),
+        };
+        let owner_id = match owner_id {
+            Some(v) => v,
+            None => env::panic(b"migrate, owner_id is required to migrate the state of version 0"),
+        };
+        let contract = DID::init_state(owner_id, DID::init_config("migrate", config));
+        // the maps of the previous layout are kept under their own key until all the DIDs are migrated
+        env::storage_write(LEGACY_STATE_KEY, &data);
+
+        let log_message = format!(
+            "migrate, state version: 0 -> {}, dids to migrate: {}",
+            STATE_VERSION,
+            old.status.len()
+        );
+        env::log(log_message.as_bytes());
+        contract
+    }
+
+    /// migrate_dids
+    ///
+    /// this method will migrate up to `limit` DIDs of the first layout and return the number of steps left,
+    /// it's invoked until it returns 0. only the owner has the right to invoke this method.
+    ///
+    /// the DIDs are migrated in their registration order, a deactivated DID of the first layout has no creation time
+    /// and gets the one of the DID migrated before it, so that `get_dids_registered_after` still works.
+    /// every DID gets versionId 1, recorded as a "migrate" operation, and a storage account with a zero balance:
+    /// the bytes it already uses stay paid by the contract, the bytes of its later updates are charged to its balance.
+    ///
+    /// the entries of a DID in the maps of the previous layout are removed when it's migrated, except its status,
+    /// which keeps the position of the next DID. the statuses are removed afterwards, each of them is one more step.
+    ///
+    /// log information: "migrate_dids, migrated: 10, left: 20"
+    ///
+    pub fn migrate_dids(&mut self, limit: u64) -> u64 {
+        self.check_owner();
+        let mut old = match env::storage_read(LEGACY_STATE_KEY) {
+            Some(v) => DIDV0::try_from_slice(&v).unwrap(),
+            None => env::panic(b"migrate_dids, no migration is in progress"),
+        };
+        let start = self.dids.len();
+        let end = std::cmp::min(start.saturating_add(limit), old.status.len());
+        let mut created = start
+            .checked_sub(1)
+            .and_then(|index| self.dids.get(index))
+            .and_then(|did| self.record.get(&did))
+            .map(|record| record.created)
+            .unwrap_or(0);
+        for index in start..end {
+            let did = old.status.keys_as_vector().get(index).unwrap();
+            let status = old.status.values_as_vector().get(index).unwrap();
+            created = std::cmp::max(old.created.remove(&did).unwrap_or(0), created);
+            self.migrate_v0(&mut old, did, status, created);
+        }
+
+        let mut steps = end - start;
+        while steps < limit && !old.status.is_empty() && self.dids.len() >= old.status.len() {
+            // all the DIDs are migrated, the order of the statuses doesn't matter anymore
+            let did = old
+                .status
+                .keys_as_vector()
+                .get(old.status.len() - 1)
+                .unwrap();
+            old.status.remove(&did);
+            steps += 1;
+        }
+        let left = old.status.len() + old.status.len().saturating_sub(self.dids.len());
+        if left == 0 {
+            env::storage_remove(LEGACY_STATE_KEY);
+        } else {
+            env::storage_write(LEGACY_STATE_KEY, &old.try_to_vec().unwrap());
+        }
+
+        let log_message = format!("migrate_dids, migrated: {}, left: {}", end - start, left);
+        env::log(log_message.as_bytes());
+        left
+    }
+}
+
+impl DID {
+    /// panic if the DIDs of the first layout are being migrated.
+    pub(crate) fn check_not_migrating(method: &str) {
+        if env::storage_has_key(LEGACY_STATE_KEY) {
+            env::panic(format!("{}, migration is in progress", method).as_bytes())
+        }
+    }
+
+    fn migrate_v0(&mut self, old: &mut DIDV0, did: String, status: Status, created: u64) {
+        let mut record = DidRecord::new(created);
+        let updated = old.updated.remove(&did);
+        let contexts = old.contexts.remove(&did);
+        let public_key_list = old.public_key.remove(&did);
+        let authentication = old.authentication.remove(&did);
+        let controllers = old.controller.remove(&did);
+        let services = old.service.remove(&did);
+        match status {
+            Status::VALID => {
+                record.updated = updated.unwrap_or(created);
+                record.contexts = contexts.unwrap_or(vec![]);
+                for public_key in public_key_list.unwrap().public_key_list {
+                    self.push_key(&did, &mut record, public_key);
+                }
+                record.authentication = authentication.unwrap_or(vec![]);
+                record.controllers = controllers.unwrap_or(vec![]);
+                for controller in record.controllers.iter() {
+                    self.index_controller(controller, &did);
+                }
+                for v in services.unwrap_or(vec![]) {
+                    let ser = Service {
+                        id: v.id,
+                        tp: ServiceType::Type(v.tp),
+                        service_endpoint: ServiceEndpoint::Uri(v.service_endpoint),
+                        properties: JsonMap::default(),
+                    };
+                    self.service.insert(&service_key(&did, &ser.id, 1), &ser);
+                    record.services.push((ser.id, 1));
+                }
+            }
+            // the previous layout removed everything but the status of a deactivated DID
+            Status::DEACTIVATED => record.status = Status::DEACTIVATED,
+        }
+        // the previous layout kept the index of deactivated keys in the authentication list
+        let record = self.repair_record(&did, record);
+        self.dids.push(&did);
+        self.save_record(&did, record, "migrate", None);
+        self.storage_account
+            .insert(&did, &StorageAccount::default());
+    }
+}
@@ -0,0 +1,167 @@
+//! JSON Patch
+//!
+//! The operations of JSON Patch, RFC 6902, applied to a JSON value. The locations are JSON Pointers, RFC 6901.
+//! `patch_document` applies them to the document of a DID and stores the result.
+
+use super::*;
+use serde_json::Value;
+
+/// an operation of JSON Patch, such as {"op": "add", "path": "/controller/-", "value": "did:near:abcde.testnet"}.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum JsonPatchOp {
+    Add { path: String, value: Value },
+    Remove { path: String },
+    Replace { path: String, value: Value },
+    Move { from: String, path: String },
+    Copy { from: String, path: String },
+    Test { path: String, value: Value },
+}
+
+impl JsonPatchOp {
+    /// the locations changed by the operation, "from" of "move" is removed.
+    pub fn changed_paths(&self) -> Vec<&String> {
+        match self {
+            JsonPatchOp::Add { path, .. }
+            | JsonPatchOp::Remove { path }
+            | JsonPatchOp::Replace { path, .. }
+            | JsonPatchOp::Copy { path, .. } => vec![path],
+            JsonPatchOp::Move { from, path } => vec![from, path],
+            JsonPatchOp::Test { .. } => vec![],
+        }
+    }
+}
+
+/// the reference tokens of the JSON Pointer, the root is "".
+pub fn parse_pointer(pointer: &str) -> Result<Vec<String>, String> {
+    if pointer.is_empty() {
+        return Ok(vec![]);
+    }
+    if !pointer.starts_with('/') {
+        return Err(format!("invalid path \"{}\"", pointer));
+    }
+    let mut tokens = vec![];
+    for token in pointer[1..].split('/') {
+        let mut result = String::new();
+        let mut chars = token.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '~' => match chars.next() {
+                    Some('0') => result.push('~'),
+                    Some('1') => result.push('/'),
+                    _ => return Err(format!("invalid path \"{}\"", pointer)),
+                },
+                _ => result.push(c),
+            }
+        }
+        tokens.push(result);
+    }
+    Ok(tokens)
+}
+
+/// the array index of the token, "-" is the end of the array if `allow_end` is true.
+fn array_index(token: &str, len: usize, allow_end: bool) -> Option<usize> {
+    if allow_end && token == "-" {
+        return Some(len);
+    }
+    if token.is_empty()
+        || (token.len() > 1 && token.starts_with('0'))
+        || !token.chars().all(|c| c.is_ascii_digit())
+    {
+        return None;
+    }
+    let index: usize = token.parse().ok()?;
+    let in_range = if allow_end { index <= len } else { index < len };
+    if !in_range {
+        return None;
+    }
+    Some(index)
+}
+
+fn get<'a>(value: &'a Value, tokens: &[String]) -> Option<&'a Value> {
+    tokens.iter().try_fold(value, |v, token| match v {
+        Value::Object(map) => map.get(token),
+        Value::Array(array) => array_index(token, array.len(), false).map(|i| &array[i]),
+        _ => None,
+    })
+}
+
+fn get_mut<'a>(value: &'a mut Value, tokens: &[String]) -> Option<&'a mut Value> {
+    tokens.iter().try_fold(value, |v, token| match v {
+        Value::Object(map) => map.get_mut(token),
+        Value::Array(array) => {
+            let index = array_index(token, array.len(), false)?;
+            Some(&mut array[index])
+        }
+        _ => None,
+    })
+}
+
+fn add(document: &mut Value, path: &str, value: Value) -> Result<(), String> {
+    let tokens = parse_pointer(path)?;
+    let (last, parent) = match tokens.split_last() {
+        Some(v) => v,
+        None => {
+            *document = value;
+            return Ok(());
+        }
+    };
+    match get_mut(document, parent) {
+        Some(Value::Object(map)) => {
+            map.insert(last.clone(), value);
+        }
+        Some(Value::Array(array)) => match array_index(last, array.len(), true) {
+            Some(i) => array.insert(i, value),
+            None => return Err(format!("invalid array index in \"{}\"", path)),
+        },
+        _ => return Err(format!("path \"{}\" doesn't exist", path)),
+    }
+    Ok(())
+}
+
+fn remove(document: &mut Value, path: &str) -> Result<Value, String> {
+    let tokens = parse_pointer(path)?;
+    let (last, parent) = match tokens.split_last() {
+        Some(v) => v,
+        None => return Err("the document can't be removed".to_string()),
+    };
+    let removed = match get_mut(document, parent) {
+        Some(Value::Object(map)) => map.remove(last),
+        Some(Value::Array(array)) => array_index(last, array.len(), false).map(|i| array.remove(i)),
+        _ => None,
+    };
+    removed.ok_or_else(|| format!("path \"{}\" doesn't exist", path))
+}
+
+/// apply the operation to the document, the document is left partly changed if it fails.
+pub fn apply_patch_op(document: &mut Value, op: &JsonPatchOp) -> Result<(), String> {
+    match op {
+        JsonPatchOp::Add { path, value } => add(document, path, value.clone()),
+        JsonPatchOp::Remove { path } => remove(document, path).map(|_| ()),
+        JsonPatchOp::Replace { path, value } => {
+            match get_mut(document, &parse_pointer(path)?) {
+                Some(v) => *v = value.clone(),
+                None => return Err(format!("path \"{}\" doesn't exist", path)),
+            }
+            Ok(())
+        }
+        JsonPatchOp::Move { from, path } => {
+            if path.starts_with(&format!("{}/", from)) {
+                return Err(format!("\"{}\" can't be moved into itself", from));
+            }
+            let value = remove(document, from)?;
+            add(document, path, value)
+        }
+        JsonPatchOp::Copy { from, path } => {
+            let value = match get(document, &parse_pointer(from)?) {
+                Some(v) => v.clone(),
+                None => return Err(format!("path \"{}\" doesn't exist", from)),
+            };
+            add(document, path, value)
+        }
+        JsonPatchOp::Test { path, value } => match get(document, &parse_pointer(path)?) {
+            Some(v) if v == value => Ok(()),
+            _ => Err(format!("test of \"{}\" failed", path)),
+        },
+    }
+}
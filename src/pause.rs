@@ -0,0 +1,114 @@
+//! pause
+//!
+//! The mutations are divided into groups which can be paused separately, so that a bug in one of them
+//! can be contained without stopping the others. The view methods are never paused.
+
+use super::*;
+
+#[derive(
+    BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, PartialEq, Debug,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum PauseGroup {
+    /// reg_did_using_account, reg_did_using_key, reg_did_using_predecessor, reg_did_with_document, create_account_and_did,
+    /// bind_account and deactivate_did.
+    Registration,
+    /// add_key, deactivate_key, add_new_auth_key, set_auth_key and deactivate_auth_key, and the patches of publicKey and authentication.
+    Keys,
+    /// add_controller, remove_controller, add_new_auth_key_by_controller, set_auth_key_by_controller and
+    /// deactivate_auth_key_by_controller, and the patches of controller.
+    Controllers,
+    /// add_service, update_service and remove_service, and the patches of service.
+    Services,
+    /// add_context, remove_context and set_contexts, and the patches of @contexts.
+    Contexts,
+    /// storage_deposit and storage_withdraw.
+    Storage,
+    /// add_also_known_as and remove_also_known_as, and the patches of alsoKnownAs.
+    AlsoKnownAs,
+}
+
+impl PauseGroup {
+    pub fn all() -> Vec<PauseGroup> {
+        vec![
+            PauseGroup::Registration,
+            PauseGroup::Keys,
+            PauseGroup::Controllers,
+            PauseGroup::Services,
+            PauseGroup::Contexts,
+            PauseGroup::Storage,
+            PauseGroup::AlsoKnownAs,
+        ]
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PauseGroup::Registration => "registration",
+            PauseGroup::Keys => "keys",
+            PauseGroup::Controllers => "controllers",
+            PauseGroup::Services => "services",
+            PauseGroup::Contexts => "contexts",
+            PauseGroup::Storage => "storage",
+            PauseGroup::AlsoKnownAs => "also_known_as",
+        }
+    }
+}
+
+#[near_bindgen]
+impl DID {
+    /// pause
+    ///
+    /// this method will pause the mutations of `groups`, all the mutations are paused if `groups` is None.
+    /// only the owner, the admins and the operators have the right to invoke this method.
+    ///
+    /// log information: EVENT_JSON:{"standard":"did-near","version":"1.0.0","event":"pause","data":[{"groups":["keys"],"paused":["keys"]}]}
+    ///
+    pub fn pause(&mut self, groups: Option<Vec<PauseGroup>>) {
+        self.check_operator();
+        let groups = groups.unwrap_or_else(PauseGroup::all);
+        for group in groups.iter() {
+            if !self.paused.contains(group) {
+                self.paused.push(*group);
+            }
+        }
+        emit_event(
+            "pause",
+            serde_json::json!({ "groups": groups, "paused": self.paused }),
+        );
+    }
+
+    /// unpause
+    ///
+    /// this method will resume the mutations of `groups`, all the mutations are resumed if `groups` is None.
+    /// only the owner and the admins have the right to invoke this method.
+    ///
+    /// log information: EVENT_JSON:{"standard":"did-near","version":"1.0.0","event":"unpause","data":[{"groups":["keys"],"paused":[]}]}
+    ///
+    pub fn unpause(&mut self, groups: Option<Vec<PauseGroup>>) {
+        self.check_admin();
+        let groups = groups.unwrap_or_else(PauseGroup::all);
+        self.paused.retain(|x| !groups.contains(x));
+        emit_event(
+            "unpause",
+            serde_json::json!({ "groups": groups, "paused": self.paused }),
+        );
+    }
+
+    /// get_paused
+    ///
+    /// this method query the paused groups of mutations.
+    ///
+    pub fn get_paused(&self) -> Vec<PauseGroup> {
+        self.paused.clone()
+    }
+}
+
+impl DID {
+    /// panic if the mutations of `group` are paused, or if the DIDs of the first layout are being migrated.
+    pub(crate) fn check_not_paused(&self, method: &str, group: PauseGroup) {
+        DID::check_not_migrating(method);
+        if self.paused.contains(&group) {
+            env::panic(format!("{}, {} is paused", method, group.as_str()).as_bytes())
+        }
+    }
+}
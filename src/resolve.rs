@@ -0,0 +1,105 @@
+//! resolution
+//!
+//! `resolve` returns the document of a DID with the document metadata and the resolution metadata of
+//! DID Resolution, so that a resolver can return the result as it is.
+
+use super::*;
+
+/// the media type of the document.
+pub const DID_CONTENT_TYPE: &'static str = "application/did+ld+json";
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct DocumentMetadata {
+    pub created: u64,
+    pub updated: u64,
+    #[serde(rename(serialize = "versionId", deserialize = "versionId"))]
+    pub version_id: u64,
+    pub deactivated: bool,
+    /// the DIDs of this contract which are verified to identify the same subject, they link to each other by alsoKnownAs.
+    /// they are other DIDs, so they aren't the equivalentId of DID Core, which are other forms of the same DID.
+    #[serde(
+        default,
+        rename(serialize = "verifiedAlsoKnownAs", deserialize = "verifiedAlsoKnownAs"),
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub verified_also_known_as: Vec<String>,
+    #[serde(
+        default,
+        rename(serialize = "canonicalId", deserialize = "canonicalId"),
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub canonical_id: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ResolutionMetadata {
+    #[serde(rename(serialize = "contentType", deserialize = "contentType"))]
+    pub content_type: String,
+    /// "invalidDid" or "notFound" if the DID can't be resolved.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ResolutionResult {
+    #[serde(rename(serialize = "didDocument", deserialize = "didDocument"))]
+    pub did_document: Option<Document>,
+    #[serde(rename(serialize = "didDocumentMetadata", deserialize = "didDocumentMetadata"))]
+    pub did_document_metadata: DocumentMetadata,
+    #[serde(rename(
+        serialize = "didResolutionMetadata",
+        deserialize = "didResolutionMetadata"
+    ))]
+    pub did_resolution_metadata: ResolutionMetadata,
+}
+
+#[near_bindgen]
+impl DID {
+    /// resolve
+    ///
+    /// this method query the document of the DID with its metadata, the result has the shape defined by DID Resolution.
+    ///
+    /// the document is null if the DID is deactivated. the alsoKnownAs links to the DIDs of this contract which link back
+    /// are the verifiedAlsoKnownAs of the metadata, and the canonicalId is the DID itself. the links to external identifiers
+    /// can't be verified by the contract, they are never in verifiedAlsoKnownAs.
+    ///
+    pub fn resolve(&self, did: String) -> ResolutionResult {
+        let mut result = ResolutionResult {
+            did_document: None,
+            did_document_metadata: DocumentMetadata::default(),
+            did_resolution_metadata: ResolutionMetadata {
+                content_type: DID_CONTENT_TYPE.to_string(),
+                error: None,
+            },
+        };
+        let prefix = self.config.did_prefix();
+        if !did.starts_with(&prefix) || did.len() == prefix.len() {
+            result.did_resolution_metadata.error = Some("invalidDid".to_string());
+            return result;
+        }
+        let record = match self.record.get(&did) {
+            Some(v) => v,
+            None => {
+                result.did_resolution_metadata.error = Some("notFound".to_string());
+                return result;
+            }
+        };
+
+        let metadata = &mut result.did_document_metadata;
+        metadata.created = record.created;
+        metadata.updated = record.updated;
+        metadata.version_id = record.version;
+        metadata.deactivated = !record.is_valid();
+        if record.is_valid() {
+            metadata.verified_also_known_as = record
+                .also_known_as
+                .iter()
+                .filter(|x| self.is_link_verified(&did, x))
+                .cloned()
+                .collect();
+            metadata.canonical_id = Some(did.clone());
+        }
+        result.did_document = self.build_document(did, &record);
+        result
+    }
+}
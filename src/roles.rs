@@ -0,0 +1,157 @@
+//! roles
+//!
+//! The owner manages the admins and can transfer the ownership, the owner and the admins manage the operators
+//! and the contract-level configuration. Every change of the roles emits an event.
+
+use super::*;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+pub struct Roles {
+    #[serde(rename(serialize = "ownerId", deserialize = "ownerId"))]
+    pub owner_id: String,
+    pub admins: Vec<String>,
+    pub operators: Vec<String>,
+}
+
+impl Roles {
+    pub fn new(owner_id: String) -> Self {
+        Roles {
+            owner_id,
+            admins: vec![],
+            operators: vec![],
+        }
+    }
+
+    pub fn is_owner(&self, account_id: &str) -> bool {
+        self.owner_id == account_id
+    }
+
+    /// the owner is an admin as well.
+    pub fn is_admin(&self, account_id: &str) -> bool {
+        self.is_owner(account_id) || self.admins.iter().any(|x| x == account_id)
+    }
+
+    /// the owner and the admins are operators as well.
+    pub fn is_operator(&self, account_id: &str) -> bool {
+        self.is_admin(account_id) || self.operators.iter().any(|x| x == account_id)
+    }
+}
+
+#[near_bindgen]
+impl DID {
+    /// transfer_ownership
+    ///
+    /// this method will transfer the ownership of the contract to `new_owner_id`. only the owner has the right to invoke this method.
+    ///
+    /// log information: EVENT_JSON:{"standard":"did-near","version":"1.0.0","event":"transfer_ownership","data":[{"old_owner_id":"alice.testnet","new_owner_id":"bob.testnet"}]}
+    ///
+    pub fn transfer_ownership(&mut self, new_owner_id: String) {
+        self.check_owner();
+        let old_owner_id = std::mem::replace(&mut self.roles.owner_id, new_owner_id.clone());
+        emit_event(
+            "transfer_ownership",
+            serde_json::json!({ "old_owner_id": old_owner_id, "new_owner_id": new_owner_id }),
+        );
+    }
+
+    /// add_admin
+    ///
+    /// this method will add an admin. only the owner has the right to invoke this method.
+    ///
+    /// log information: EVENT_JSON:{"standard":"did-near","version":"1.0.0","event":"add_admin","data":[{"account_id":"bob.testnet"}]}
+    ///
+    pub fn add_admin(&mut self, account_id: String) {
+        self.check_owner();
+        if self.roles.admins.contains(&account_id) {
+            env::panic(b"add_admin, account is admin")
+        }
+        self.roles.admins.push(account_id.clone());
+        emit_event("add_admin", serde_json::json!({ "account_id": account_id }));
+    }
+
+    /// remove_admin
+    ///
+    /// this method will remove an admin. only the owner has the right to invoke this method.
+    ///
+    /// log information: EVENT_JSON:{"standard":"did-near","version":"1.0.0","event":"remove_admin","data":[{"account_id":"bob.testnet"}]}
+    ///
+    pub fn remove_admin(&mut self, account_id: String) {
+        self.check_owner();
+        match self.roles.admins.iter().position(|x| x == &account_id) {
+            Some(ind) => {
+                self.roles.admins.remove(ind);
+            }
+            None => env::panic(b"remove_admin, account is not admin"),
+        }
+        emit_event(
+            "remove_admin",
+            serde_json::json!({ "account_id": account_id }),
+        );
+    }
+
+    /// add_operator
+    ///
+    /// this method will add an operator. only the owner and the admins have the right to invoke this method.
+    ///
+    /// log information: EVENT_JSON:{"standard":"did-near","version":"1.0.0","event":"add_operator","data":[{"account_id":"bob.testnet"}]}
+    ///
+    pub fn add_operator(&mut self, account_id: String) {
+        self.check_admin();
+        if self.roles.operators.contains(&account_id) {
+            env::panic(b"add_operator, account is operator")
+        }
+        self.roles.operators.push(account_id.clone());
+        emit_event(
+            "add_operator",
+            serde_json::json!({ "account_id": account_id }),
+        );
+    }
+
+    /// remove_operator
+    ///
+    /// this method will remove an operator. only the owner and the admins have the right to invoke this method.
+    ///
+    /// log information: EVENT_JSON:{"standard":"did-near","version":"1.0.0","event":"remove_operator","data":[{"account_id":"bob.testnet"}]}
+    ///
+    pub fn remove_operator(&mut self, account_id: String) {
+        self.check_admin();
+        match self.roles.operators.iter().position(|x| x == &account_id) {
+            Some(ind) => {
+                self.roles.operators.remove(ind);
+            }
+            None => env::panic(b"remove_operator, account is not operator"),
+        }
+        emit_event(
+            "remove_operator",
+            serde_json::json!({ "account_id": account_id }),
+        );
+    }
+
+    /// get_roles
+    ///
+    /// this method query the owner, the admins and the operators of the contract.
+    ///
+    pub fn get_roles(&self) -> Roles {
+        self.roles.clone()
+    }
+}
+
+impl DID {
+    pub(crate) fn check_owner(&self) {
+        if !self.roles.is_owner(&env::predecessor_account_id()) {
+            env::panic(b"only the owner can invoke this method")
+        }
+    }
+
+    pub(crate) fn check_admin(&self) {
+        if !self.roles.is_admin(&env::predecessor_account_id()) {
+            env::panic(b"only the owner or admins can invoke this method")
+        }
+    }
+
+    pub(crate) fn check_operator(&self) {
+        if !self.roles.is_operator(&env::predecessor_account_id()) {
+            env::panic(b"only the owner, admins or operators can invoke this method")
+        }
+    }
+}
@@ -0,0 +1,215 @@
+//! storage management
+//!
+//! This implements the storage management standard NEP-145. Every DID pays for the storage it uses:
+//! the deposit attached to any method that updates a DID is added to its storage balance,
+//! the storage used by the update is charged against the balance, and the storage freed is refunded to it.
+//!
+//! The previous versions of a document are kept with their keys and services, and every update writes a version
+//! and an operation log entry. So a removal only frees the bytes of the current record and of the indexes, which is
+//! usually less than the version it writes, and its net charge is positive. The storage of the history is never freed,
+//! `deactivate_did` refunds the balance which is not used by it.
+
+use super::*;
+
+/// the price of one byte of storage, 10^19 yoctoNEAR.
+pub const STORAGE_PRICE_PER_BYTE: u128 = 10_000_000_000_000_000_000;
+/// the storage balance required to register, it covers the storage of a DID with one key.
+pub const MIN_STORAGE_BYTES: u64 = 1_000;
+
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct StorageAccount {
+    /// the total deposit, in yoctoNEAR.
+    pub total: u128,
+    /// the bytes used by the DID.
+    pub used_bytes: u64,
+}
+
+impl StorageAccount {
+    pub fn used(&self) -> u128 {
+        self.used_bytes as u128 * STORAGE_PRICE_PER_BYTE
+    }
+
+    pub fn available(&self) -> u128 {
+        self.total.saturating_sub(self.used())
+    }
+
+    pub fn to_balance(&self) -> StorageBalance {
+        StorageBalance {
+            total: self.total.into(),
+            available: self.available().into(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StorageBalance {
+    pub total: U128,
+    pub available: U128,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StorageBalanceBounds {
+    pub min: U128,
+    pub max: Option<U128>,
+}
+
+#[near_bindgen]
+impl DID {
+    /// storage_deposit
+    ///
+    /// this method will add the attached deposit to the storage balance of the DID of `account_id`, which is the DID bound to it
+    /// or the DID generated from it. the default is the DID of the caller, as it's resolved by the methods updating a DID.
+    ///
+    /// if `registration_only` is true, only the minimum balance is kept and the rest is refunded.
+    ///
+    /// log information: "storage_deposit, did: did:near:abcde.testnet, amount: 1000"
+    ///
+    pub fn storage_deposit(
+        &mut self,
+        account_id: Option<String>,
+        registration_only: Option<bool>,
+    ) -> StorageBalance {
+        self.check_not_paused("storage_deposit", PauseGroup::Storage);
+        let initial_storage = env::storage_usage();
+        let did = match account_id {
+            Some(v) => self.account_did(&v),
+            None => self.caller_did(),
+        };
+        let mut amount = env::attached_deposit();
+
+        if registration_only.unwrap_or(false) {
+            let min = MIN_STORAGE_BYTES as u128 * STORAGE_PRICE_PER_BYTE;
+            let refund = if self.storage_account.get(&did).is_some() {
+                amount
+            } else if amount < min {
+                env::panic(b"storage_deposit, attached deposit is less than the minimum")
+            } else {
+                amount - min
+            };
+            if refund > 0 {
+                Promise::new(env::predecessor_account_id()).transfer(refund);
+            }
+            amount -= refund;
+        }
+
+        let mut storage_account = self.storage_account.get(&did).unwrap_or_default();
+        storage_account.total += amount;
+        self.storage_account.insert(&did, &storage_account);
+        self.charge_storage_without_deposit(&did, initial_storage);
+
+        let log_message = format!("storage_deposit, did: {}, amount: {}", &did, amount);
+        env::log(log_message.as_bytes());
+        self.storage_account.get(&did).unwrap().to_balance()
+    }
+
+    /// storage_withdraw
+    ///
+    /// this method will withdraw `amount` of the available storage balance of the caller's DID to the account owning it,
+    /// all the available balance is withdrawn if `amount` is None. exactly 1 yoctoNEAR must be attached.
+    /// the caller must have access to the DID if it's valid. the balance of a DID authorized by predecessor goes to
+    /// the predecessor account, even after it's deactivated, the balance of any other DID goes to the signer account.
+    ///
+    /// log information: "storage_withdraw, did: did:near:abcde.testnet, amount: 1000"
+    ///
+    pub fn storage_withdraw(&mut self, amount: Option<U128>) -> StorageBalance {
+        self.check_not_paused("storage_withdraw", PauseGroup::Storage);
+        if env::attached_deposit() != 1 {
+            env::panic(b"storage_withdraw, requires attached deposit of exactly 1 yoctoNEAR")
+        }
+        let did = self.caller_did();
+        let account_id = match self.record.get(&did) {
+            Some(record) => {
+                // a deactivated DID has no keys left, the caller resolving to it owns it
+                if record.is_valid() {
+                    self.check_access(&did, &record);
+                }
+                self.caller_account(&record)
+            }
+            // the caller of an unregistered DID is the signer, as `caller_did` resolves it
+            None => env::signer_account_id(),
+        };
+        let mut storage_account = match self.storage_account.get(&did) {
+            Some(v) => v,
+            None => env::panic(b"storage_withdraw, account is not registered"),
+        };
+        let available = storage_account.available();
+        let amount = amount.map(|x| x.0).unwrap_or(available);
+        if amount > available {
+            env::panic(b"storage_withdraw, amount is more than the available balance")
+        }
+        storage_account.total -= amount;
+        self.storage_account.insert(&did, &storage_account);
+        // the attached yoctoNEAR is returned as well
+        Promise::new(account_id).transfer(amount + 1);
+
+        let log_message = format!("storage_withdraw, did: {}, amount: {}", &did, amount);
+        env::log(log_message.as_bytes());
+        storage_account.to_balance()
+    }
+
+    /// storage_balance_of
+    ///
+    /// this method query the storage balance of the DID of `account_id`, the DID bound to it or the DID generated from it.
+    ///
+    pub fn storage_balance_of(&self, account_id: String) -> Option<StorageBalance> {
+        self.storage_account
+            .get(&self.account_did(&account_id))
+            .map(|x| x.to_balance())
+    }
+
+    /// storage_balance_bounds
+    ///
+    /// this method query the minimum storage balance to register, there is no maximum.
+    ///
+    pub fn storage_balance_bounds(&self) -> StorageBalanceBounds {
+        StorageBalanceBounds {
+            min: (MIN_STORAGE_BYTES as u128 * STORAGE_PRICE_PER_BYTE).into(),
+            max: None,
+        }
+    }
+}
+
+impl DID {
+    /// add the attached deposit to the storage balance of the DID, then charge the storage used since `initial_storage`.
+    pub(crate) fn charge_storage(&mut self, did: &String, initial_storage: u64) {
+        let mut storage_account = self.storage_account.get(did).unwrap_or_default();
+        storage_account.total += env::attached_deposit();
+        self.storage_account.insert(did, &storage_account);
+        self.charge_storage_without_deposit(did, initial_storage);
+    }
+
+    fn charge_storage_without_deposit(&mut self, did: &String, initial_storage: u64) {
+        // the callers insert the account after measuring `initial_storage`, so the bytes of a new account are charged
+        // to it as well, updating an existing account doesn't change the usage since it has a fixed size
+        let mut storage_account = self.storage_account.get(did).unwrap();
+        let current_storage = env::storage_usage();
+        if current_storage >= initial_storage {
+            storage_account.used_bytes += current_storage - initial_storage;
+            if storage_account.used() > storage_account.total {
+                env::panic(
+                    format!(
+                        "insufficient storage balance, {} yoctoNEAR is required",
+                        storage_account.used() - storage_account.total
+                    )
+                    .as_bytes(),
+                )
+            }
+        } else {
+            storage_account.used_bytes = storage_account
+                .used_bytes
+                .saturating_sub(initial_storage - current_storage);
+        }
+        self.storage_account.insert(did, &storage_account);
+    }
+
+    /// transfer the available storage balance of the DID to `account_id`.
+    pub(crate) fn refund_storage(&mut self, did: &String, account_id: &str) {
+        let mut storage_account = self.storage_account.get(did).unwrap();
+        let available = storage_account.available();
+        if available > 0 {
+            storage_account.total -= available;
+            self.storage_account.insert(did, &storage_account);
+            Promise::new(account_id.to_string()).transfer(available);
+        }
+    }
+}
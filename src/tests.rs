@@ -1,23 +1,41 @@
 use super::*;
+use near_sdk::collections::UnorderedMap;
+use near_sdk::json_types::U64;
 use near_sdk::MockedBlockchain;
+use near_sdk::PromiseResult;
 use near_sdk::{testing_env, VMContext};
+use std::collections::{BTreeMap, HashMap};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+fn default_pk() -> Vec<u8> {
+    vec![
+        0, 59, 217, 200, 51, 244, 152, 125, 173, 92, 30, 224, 60, 141, 221, 44, 65, 132, 45, 94,
+        199, 150, 116, 108, 95, 18, 118, 246, 86, 167, 64, 132, 76,
+    ]
+}
 
 fn get_context(signer_id: String, input: Vec<u8>, is_view: bool) -> VMContext {
+    get_context_with_pk(signer_id, default_pk(), input, is_view)
+}
+
+fn get_context_with_pk(
+    signer_id: String,
+    signer_pk: Vec<u8>,
+    input: Vec<u8>,
+    is_view: bool,
+) -> VMContext {
     VMContext {
         current_account_id: "alice_near".to_string(),
         signer_account_id: signer_id,
-        signer_account_pk: vec![
-            0, 59, 217, 200, 51, 244, 152, 125, 173, 92, 30, 224, 60, 141, 221, 44, 65, 132, 45,
-            94, 199, 150, 116, 108, 95, 18, 118, 246, 86, 167, 64, 132, 76,
-        ],
+        signer_account_pk: signer_pk,
         predecessor_account_id: "carol_near".to_string(),
         input,
         block_index: 0,
         block_timestamp: 0,
-        account_balance: 0,
+        account_balance: 10u128.pow(30),
         account_locked_balance: 0,
         storage_usage: 0,
-        attached_deposit: 0,
+        attached_deposit: 10u128.pow(25),
         prepaid_gas: 10u64.pow(18),
         random_seed: vec![0, 1, 2],
         is_view,
@@ -26,11 +44,51 @@ fn get_context(signer_id: String, input: Vec<u8>, is_view: bool) -> VMContext {
     }
 }
 
+fn take_storage() -> HashMap<Vec<u8>, Vec<u8>> {
+    env::take_blockchain_interface()
+        .unwrap()
+        .as_mut_mocked_blockchain()
+        .unwrap()
+        .take_storage()
+}
+
+fn set_blockchain(context: VMContext, storage: HashMap<Vec<u8>, Vec<u8>>) {
+    env::set_blockchain_interface(Box::new(MockedBlockchain::new(
+        context,
+        Default::default(),
+        Default::default(),
+        vec![],
+        storage,
+    )));
+}
+
+/// replace the context of the mocked blockchain but keep its storage
+fn switch_context(mut context: VMContext) {
+    context.storage_usage = env::storage_usage();
+    set_blockchain(context, take_storage());
+}
+
+/// switch to a callback of the contract which receives the results of the promises
+fn switch_to_callback(promise_results: Vec<PromiseResult>) {
+    let mut context = get_context("bob_near".to_string(), vec![], false);
+    context.predecessor_account_id = "alice_near".to_string();
+    context.attached_deposit = 0;
+    context.storage_usage = env::storage_usage();
+    let storage = take_storage();
+    env::set_blockchain_interface(Box::new(MockedBlockchain::new(
+        context,
+        Default::default(),
+        Default::default(),
+        promise_results,
+        storage,
+    )));
+}
+
 #[test]
 fn controller_test() {
     let context = get_context("bob_near".to_string(), vec![], false);
     testing_env!(context);
-    let mut contract = DID::default();
+    let mut contract = DID::new("alice_near".to_string(), None);
     contract.reg_did_using_account();
 
     //    contract.deactivate_did();
@@ -47,7 +105,7 @@ fn controller_test() {
 fn auth_key_test() {
     let context = get_context("bob_near".to_string(), vec![], false);
     testing_env!(context);
-    let mut contract = DID::default();
+    let mut contract = DID::new("alice_near".to_string(), None);
     contract.reg_did_using_account();
     //    contract.deactivate_did();
     contract.add_key(vec![1u8], "did:near:abcde.testnet".to_string());
@@ -77,10 +135,20 @@ fn auth_key_test() {
 fn service_test() {
     let context = get_context("bob_near".to_string(), vec![], false);
     testing_env!(context);
-    let mut contract = DID::default();
+    let mut contract = DID::new("alice_near".to_string(), None);
     contract.reg_did_using_account();
-    contract.add_service("id".to_string(), "tp".to_string(), "ss".to_string());
-    contract.update_service("id".to_string(), "tp2".to_string(), "ss2".to_string());
+    contract.add_service(
+        "id".to_string(),
+        ServiceType::Type("tp".to_string()),
+        ServiceEndpoint::Uri("https://ss.example.com".to_string()),
+        None,
+    );
+    contract.update_service(
+        "id".to_string(),
+        ServiceType::Type("tp2".to_string()),
+        ServiceEndpoint::Uri("https://ss2.example.com".to_string()),
+        None,
+    );
     contract.remove_service("id".to_string());
 }
 
@@ -88,9 +156,9 @@ fn service_test() {
 fn context_test() {
     let context = get_context("bob_near".to_string(), vec![], false);
     testing_env!(context);
-    let mut contract = DID::default();
+    let mut contract = DID::new("alice_near".to_string(), None);
     contract.reg_did_using_account();
-    let con = vec!["conext".to_string()];
+    let con = vec!["https://example.com/conext".to_string()];
     contract.add_context(con.clone());
     contract.remove_context(con.clone());
 
@@ -99,14 +167,16 @@ fn context_test() {
     let did = "did:near:bob_near".to_string();
     let res = contract.get_document(did.clone());
     assert!(res.is_some());
-    println!("res:{}", res.unwrap());
+    let res = res.unwrap();
+    assert!(!res.contains("https://example.com/conext"));
+    println!("res:{}", res);
 }
 
 #[test]
 fn get_document_test() {
     let context = get_context("bob_near".to_string(), vec![], false);
     testing_env!(context);
-    let mut contract = DID::default();
+    let mut contract = DID::new("alice_near".to_string(), None);
     contract.reg_did_using_account();
 
     let pk1 = vec![
@@ -122,13 +192,2984 @@ fn get_document_test() {
     contract.add_new_auth_key(pk2.clone(), "did:near:abcde.testnet".to_string());
     contract.set_auth_key(pk1.clone());
     contract.add_controller("did:near:abcde.testnet".to_string());
-    let con = vec!["conext".to_string()];
+    let con = vec!["https://example.com/conext".to_string()];
     contract.add_context(con.clone());
-    contract.add_service("id".to_string(), "tp".to_string(), "ss".to_string());
-    contract.update_service("id".to_string(), "tp2".to_string(), "ss2".to_string());
+    contract.add_service(
+        "id".to_string(),
+        ServiceType::Type("tp".to_string()),
+        ServiceEndpoint::Uri("https://ss.example.com".to_string()),
+        None,
+    );
+    contract.update_service(
+        "id".to_string(),
+        ServiceType::Type("tp2".to_string()),
+        ServiceEndpoint::Uri("https://ss2.example.com".to_string()),
+        None,
+    );
 
     let did = "did:near:bob_near".to_string();
     let res = contract.get_document(did.clone());
     assert!(res.is_some());
     println!("res:{}", res.unwrap());
 }
+
+#[test]
+fn version_test() {
+    let context = get_context("bob_near".to_string(), vec![], false);
+    testing_env!(context);
+    let mut contract = DID::new("alice_near".to_string(), None);
+    contract.reg_did_using_account();
+    let did = "did:near:bob_near".to_string();
+    let controller = "did:near:abcde.testnet".to_string();
+    contract.add_controller(controller.clone());
+    assert_eq!(contract.get_version_id(did.clone()), 2);
+
+    let v1 = contract.get_document_at(did.clone(), 1).unwrap();
+    assert!(!v1.contains(&controller));
+    let v2 = contract.get_document_at(did.clone(), 2).unwrap();
+    assert!(v2.contains(&controller));
+    assert_eq!(contract.get_document(did.clone()), Some(v2.clone()));
+    assert_eq!(contract.get_document_at_time(did.clone(), 0), Some(v2));
+
+    contract.deactivate_did();
+    assert_eq!(contract.get_version_id(did.clone()), 3);
+    assert!(contract.get_document_at(did.clone(), 3).is_none());
+    assert!(contract.get_document_at(did.clone(), 1).is_some());
+}
+
+#[test]
+fn operation_log_test() {
+    let context = get_context("bob_near".to_string(), vec![], false);
+    testing_env!(context);
+    let mut contract = DID::new("alice_near".to_string(), None);
+    contract.reg_did_using_account();
+    let did = "did:near:bob_near".to_string();
+    contract.add_controller(did.clone());
+    contract.add_new_auth_key_by_controller(did.clone(), vec![0u8, 1u8], did.clone());
+
+    let ops = contract.get_operations(did.clone(), 0, 10);
+    assert_eq!(ops.len(), 3);
+    assert_eq!(ops[0].kind, "reg_did_using_account");
+    assert_eq!(ops[0].signer_account, "bob_near");
+    assert!(ops[0].controller.is_none());
+    assert_eq!(ops[2].kind, "add_new_auth_key_by_controller");
+    assert_eq!(ops[2].controller, Some(did.clone()));
+    assert_eq!(ops[2].version_id, 3);
+
+    let ops = contract.get_operations(did.clone(), 1, 1);
+    assert_eq!(ops.len(), 1);
+    assert_eq!(ops[0].kind, "add_controller");
+    assert!(contract.get_operations(did.clone(), 3, 10).is_empty());
+}
+
+#[test]
+fn enumeration_test() {
+    let mut context = get_context("bob_near".to_string(), vec![], false);
+    context.block_timestamp = 10;
+    testing_env!(context);
+    let mut contract = DID::new("alice_near".to_string(), None);
+    contract.reg_did_using_account();
+
+    let mut context = get_context("carol_near".to_string(), vec![], false);
+    context.block_timestamp = 20;
+    switch_context(context);
+    contract.reg_did_using_account();
+    let mut context = get_context("dave_near".to_string(), vec![], false);
+    context.block_timestamp = 30;
+    switch_context(context);
+    contract.reg_did_using_account();
+    contract.deactivate_did();
+
+    assert_eq!(contract.get_did_count(), 3);
+    assert_eq!(
+        contract.get_dids(0, 10),
+        vec![
+            "did:near:bob_near".to_string(),
+            "did:near:carol_near".to_string(),
+            "did:near:dave_near".to_string()
+        ]
+    );
+    assert_eq!(
+        contract.get_dids(1, 1),
+        vec!["did:near:carol_near".to_string()]
+    );
+    let page = contract.get_dids_by_status(true, 1, 10);
+    assert_eq!(page.dids, vec!["did:near:carol_near".to_string()]);
+    assert_eq!(page.next_index, None);
+    let page = contract.get_dids_by_status(false, 0, 10);
+    assert_eq!(page.dids, vec!["did:near:dave_near".to_string()]);
+    // the page covers `limit` DIDs whatever their status
+    let page = contract.get_dids_by_status(false, 0, 2);
+    assert!(page.dids.is_empty());
+    assert_eq!(page.next_index, Some(2));
+    assert_eq!(
+        contract.get_dids_by_status(false, 2, 2).dids,
+        vec!["did:near:dave_near".to_string()]
+    );
+    assert_eq!(
+        contract.get_dids_registered_after(10, 0, 10),
+        vec![
+            "did:near:carol_near".to_string(),
+            "did:near:dave_near".to_string()
+        ]
+    );
+    assert_eq!(
+        contract.get_dids_registered_after(20, 0, 10),
+        vec!["did:near:dave_near".to_string()]
+    );
+    assert_eq!(
+        contract.get_dids_registered_after(0, 1, 1),
+        vec!["did:near:carol_near".to_string()]
+    );
+    assert!(contract.get_dids_registered_after(30, 0, 10).is_empty());
+}
+
+#[test]
+fn key_index_test() {
+    let context = get_context("bob_near".to_string(), vec![], false);
+    testing_env!(context);
+    let mut contract = DID::new("alice_near".to_string(), None);
+    contract.reg_did_using_account();
+    let pk = vec![0u8, 1u8];
+    contract.add_key(pk.clone(), "did:near:bob_near".to_string());
+
+    let context = get_context("carol_near".to_string(), vec![], false);
+    switch_context(context);
+    contract.reg_did_using_account();
+    contract.add_new_auth_key(pk.clone(), "did:near:carol_near".to_string());
+
+    let refs = contract.find_dids_by_key(pk.clone(), None);
+    assert_eq!(refs.len(), 2);
+    assert_eq!(refs[0].did, "did:near:bob_near");
+    assert_eq!(refs[0].key_id, "did:near:bob_near#keys-2");
+    let refs = contract.find_dids_by_key(pk.clone(), Some("authentication".to_string()));
+    assert_eq!(refs.len(), 1);
+    assert_eq!(refs[0].did, "did:near:carol_near");
+    assert_eq!(
+        contract
+            .find_dids_by_key(default_pk(), Some("publicKey".to_string()))
+            .len(),
+        2
+    );
+    assert!(contract
+        .find_dids_by_key(default_pk(), Some("assertionMethod".to_string()))
+        .is_empty());
+
+    contract.deactivate_did();
+    let refs = contract.find_dids_by_key(pk.clone(), None);
+    assert_eq!(refs.len(), 1);
+    assert_eq!(refs[0].did, "did:near:bob_near");
+}
+
+#[test]
+fn controlled_dids_test() {
+    let context = get_context("bob_near".to_string(), vec![], false);
+    testing_env!(context);
+    let mut contract = DID::new("alice_near".to_string(), None);
+    let acme = "did:near:acme".to_string();
+    contract.reg_did_using_account();
+    contract.add_controller(acme.clone());
+
+    let context = get_context("carol_near".to_string(), vec![], false);
+    switch_context(context);
+    contract.reg_did_using_account();
+    contract.add_controller(acme.clone());
+
+    assert_eq!(
+        contract.get_controlled_dids(acme.clone(), 0, 10),
+        vec![
+            "did:near:bob_near".to_string(),
+            "did:near:carol_near".to_string()
+        ]
+    );
+    assert_eq!(
+        contract.get_controlled_dids(acme.clone(), 1, 10),
+        vec!["did:near:carol_near".to_string()]
+    );
+
+    contract.deactivate_did();
+    assert_eq!(
+        contract.get_controlled_dids(acme.clone(), 0, 10),
+        vec!["did:near:bob_near".to_string()]
+    );
+
+    let context = get_context("bob_near".to_string(), vec![], false);
+    switch_context(context);
+    contract.remove_controller(acme.clone());
+    assert!(contract.get_controlled_dids(acme.clone(), 0, 10).is_empty());
+}
+
+#[test]
+fn rich_service_test() {
+    let context = get_context("bob_near".to_string(), vec![], false);
+    testing_env!(context);
+    let mut contract = DID::new("alice_near".to_string(), None);
+    contract.reg_did_using_account();
+
+    let service: Service = serde_json::from_str(
+        r#"{
+            "id": "didcomm",
+            "type": ["DIDCommMessaging"],
+            "serviceEndpoint": [
+                "https://example.com/didcomm",
+                {"uri": "wss://example.com/didcomm", "accept": ["didcomm/v2"]}
+            ],
+            "routingKeys": ["did:near:mediator#keys-1"]
+        }"#,
+    )
+    .unwrap();
+    assert_eq!(
+        service.tp,
+        ServiceType::Types(vec!["DIDCommMessaging".to_string()])
+    );
+    assert_eq!(
+        service.properties.get("routingKeys"),
+        Some(serde_json::json!(["did:near:mediator#keys-1"]))
+    );
+    contract.add_service(
+        service.id,
+        service.tp,
+        service.service_endpoint,
+        Some(service.properties),
+    );
+    contract.update_service(
+        "didcomm".to_string(),
+        ServiceType::Type("DIDCommMessaging".to_string()),
+        ServiceEndpoint::Uri("https://example.com/v2".to_string()),
+        None,
+    );
+
+    let did = "did:near:bob_near".to_string();
+    let doc: serde_json::Value =
+        serde_json::from_str(&contract.get_document(did).unwrap()).unwrap();
+    assert_eq!(
+        doc["service"][0],
+        serde_json::json!({
+            "id": "did:near:bob_near#didcomm",
+            "type": "DIDCommMessaging",
+            "serviceEndpoint": "https://example.com/v2",
+            "routingKeys": ["did:near:mediator#keys-1"]
+        })
+    );
+}
+
+#[test]
+fn service_validation_test() {
+    let schemes = Config::default().endpoint_schemes;
+    assert!(check_service_id("didcomm-1").is_ok());
+    assert!(check_service_id("").is_err());
+    assert!(check_service_id("a#b").is_err());
+    assert!(check_service_id("a b").is_err());
+    assert!(check_service_id("a%2Fb").is_ok());
+    assert!(check_service_id("a%2").is_err());
+
+    assert!(check_uri("https://example.com/path?q=1#f", &schemes).is_ok());
+    assert!(check_uri("did:near:bob_near", &schemes).is_ok());
+    assert!(check_uri("ss", &schemes).is_err());
+    assert!(check_uri("ftp://example.com", &schemes).is_err());
+    assert!(check_uri("https://", &schemes).is_err());
+    assert!(check_uri("https://exa mple.com", &schemes).is_err());
+
+    let registry = vec!["LinkedDomains".to_string()];
+    assert!(check_service_type(&ServiceType::Type("LinkedDomains".to_string()), &registry).is_ok());
+    assert!(check_service_type(&ServiceType::Type("Other".to_string()), &registry).is_err());
+    assert!(check_service_type(&ServiceType::Types(vec![]), &[]).is_err());
+}
+
+#[test]
+#[should_panic(expected = "is not registered")]
+fn service_type_registry_test() {
+    let mut context = get_context("bob_near".to_string(), vec![], false);
+    context.predecessor_account_id = context.current_account_id.clone();
+    testing_env!(context);
+    let mut contract = DID::new("alice_near".to_string(), None);
+    contract.set_service_types(vec!["LinkedDomains".to_string()]);
+    contract.reg_did_using_account();
+    contract.add_service(
+        "id".to_string(),
+        ServiceType::Type("tp".to_string()),
+        ServiceEndpoint::Uri("https://example.com".to_string()),
+        None,
+    );
+}
+
+#[test]
+fn storage_management_test() {
+    let mut context = get_context("bob_near".to_string(), vec![], false);
+    context.attached_deposit = 10u128.pow(24);
+    testing_env!(context);
+    let mut contract = DID::new("alice_near".to_string(), None);
+    contract.reg_did_using_account();
+    let balance = contract.storage_balance_of("bob_near".to_string()).unwrap();
+    assert_eq!(balance.total.0, 10u128.pow(24));
+    let available = balance.available.0;
+    assert!(available < balance.total.0);
+
+    let mut context = get_context("bob_near".to_string(), vec![], false);
+    context.attached_deposit = 0;
+    switch_context(context);
+    let mut properties = serde_json::Map::new();
+    properties.insert("note".to_string(), serde_json::json!("x".repeat(2000)));
+    let properties: JsonMap =
+        serde_json::from_value(serde_json::Value::Object(properties)).unwrap();
+    contract.add_service(
+        "id".to_string(),
+        ServiceType::Type("tp".to_string()),
+        ServiceEndpoint::Uri("https://example.com".to_string()),
+        Some(properties),
+    );
+    let after_add = contract
+        .storage_balance_of("bob_near".to_string())
+        .unwrap()
+        .available
+        .0;
+    assert!(after_add < available);
+    contract.remove_service("id".to_string());
+    let after_remove = contract
+        .storage_balance_of("bob_near".to_string())
+        .unwrap()
+        .available
+        .0;
+    // the service is kept for the previous versions, only the bytes of the current record are freed,
+    // and the new version and operation log cost more than that
+    assert!(after_remove < after_add);
+
+    let mut context = get_context("bob_near".to_string(), vec![], false);
+    context.predecessor_account_id = "bob_near".to_string();
+    context.attached_deposit = 1;
+    switch_context(context);
+    let balance = contract.storage_withdraw(None);
+    assert_eq!(balance.available.0, 0);
+}
+
+#[test]
+fn predecessor_storage_withdraw_test() {
+    let mut context = get_context("bob_near".to_string(), vec![], false);
+    context.predecessor_account_id = "dao_near".to_string();
+    testing_env!(context.clone());
+    let mut contract = DID::new("alice_near".to_string(), None);
+    contract.reg_did_using_predecessor();
+    contract.deactivate_did();
+    contract.storage_deposit(None, None);
+
+    // the balance of the deactivated DID goes to the DAO, not to the member signing the transaction
+    context.attached_deposit = 1;
+    switch_context(context);
+    let balance = contract.storage_withdraw(None);
+    assert_eq!(balance.available.0, 0);
+    let receipts = serde_json::to_value(env::created_receipts()).unwrap();
+    assert_eq!(receipts[0]["receiver_id"], "dao_near");
+}
+
+#[test]
+#[should_panic(expected = "insufficient storage balance")]
+fn insufficient_storage_test() {
+    let mut context = get_context("bob_near".to_string(), vec![], false);
+    context.attached_deposit = 0;
+    testing_env!(context);
+    let mut contract = DID::new("alice_near".to_string(), None);
+    contract.reg_did_using_account();
+}
+
+#[test]
+#[should_panic(expected = "too many public keys")]
+fn limits_test() {
+    let mut context = get_context("bob_near".to_string(), vec![], false);
+    context.predecessor_account_id = context.current_account_id.clone();
+    testing_env!(context);
+    let mut contract = DID::new("alice_near".to_string(), None);
+    let mut limits = contract.get_limits();
+    assert_eq!(limits, Limits::default());
+    limits.max_public_keys = 2;
+    contract.set_limits(limits.clone());
+    assert_eq!(contract.get_limits(), limits);
+
+    contract.reg_did_using_account();
+    contract.add_key(vec![0u8, 1u8], "did:near:bob_near".to_string());
+    contract.add_key(vec![0u8, 2u8], "did:near:bob_near".to_string());
+}
+
+#[test]
+#[should_panic(expected = "too many contexts")]
+fn context_limit_test() {
+    let context = get_context("bob_near".to_string(), vec![], false);
+    testing_env!(context);
+    let mut contract = DID::new("alice_near".to_string(), None);
+    contract.reg_did_using_account();
+    let contexts = (0..17)
+        .map(|i| format!("https://example.com/{}", i))
+        .collect();
+    contract.add_context(contexts);
+}
+
+#[test]
+fn key_gas_test() {
+    let context = get_context("bob_near".to_string(), vec![], false);
+    testing_env!(context);
+    let mut contract = DID::new("alice_near".to_string(), None);
+    contract.reg_did_using_account();
+
+    let mut add_key_gas = vec![];
+    for i in 1..31u8 {
+        let mut pk = default_pk();
+        pk[32] = i;
+        let used_gas = env::used_gas();
+        contract.add_key(pk, "did:near:bob_near".to_string());
+        add_key_gas.push(env::used_gas() - used_gas);
+    }
+    // every key is stored separately, adding a key costs the same whatever the number of keys is
+    let first = add_key_gas[1];
+    let last = add_key_gas[add_key_gas.len() - 1];
+    assert!(last * 100 <= first * 105);
+
+    let mut deactivate_key_gas = vec![];
+    for i in [1u8, 30].iter() {
+        let mut pk = default_pk();
+        pk[32] = *i;
+        let used_gas = env::used_gas();
+        contract.deactivate_key(pk);
+        deactivate_key_gas.push(env::used_gas() - used_gas);
+    }
+    assert!(deactivate_key_gas[1] * 100 <= deactivate_key_gas[0] * 105);
+
+    // the same keys added to the DID of the first layout, loaded from the legacy fixture
+    let context = get_context("bob_near".to_string(), vec![], false);
+    testing_env!(context);
+    write_state_v0();
+    let mut old: DIDV0 = env::state_read().unwrap();
+    let did = "did:near:bob_near".to_string();
+    let mut add_key_gas_v0 = vec![];
+    for i in 1..31u8 {
+        let mut pk = default_pk();
+        pk[32] = i;
+        let used_gas = env::used_gas();
+        add_key_v0(&mut old, &did, pk);
+        add_key_gas_v0.push(env::used_gas() - used_gas);
+    }
+    // the whole key list is read and written back, its cost grows with every key
+    let first_v0 = add_key_gas_v0[1];
+    let last_v0 = add_key_gas_v0[add_key_gas_v0.len() - 1];
+    assert!(last_v0 > first_v0);
+    assert!(last_v0 - first_v0 > last.saturating_sub(first));
+}
+
+#[test]
+fn key_lookup_gas_test() {
+    let context = get_context("bob_near".to_string(), vec![], false);
+    testing_env!(context.clone());
+    let mut contract = DID::new("alice_near".to_string(), None);
+    contract.reg_did_using_account();
+    let used_gas = env::used_gas();
+    contract.verify_signature();
+    let before = env::used_gas() - used_gas;
+
+    // other DIDs reference the signer key of bob, the key index of the key grows
+    for i in 0..20 {
+        let context = get_context(format!("user{}_near", i), vec![], false);
+        switch_context(context);
+        contract.reg_did_using_account();
+    }
+    assert_eq!(contract.find_dids_by_key(default_pk(), None).len(), 21);
+
+    // the key of bob is found through the key lookup of his DID, it costs the same
+    switch_context(context);
+    let used_gas = env::used_gas();
+    contract.verify_signature();
+    let after = env::used_gas() - used_gas;
+    assert!(after * 100 <= before * 105);
+}
+
+/// the `add_key` of the first layout, the key list of the DID is read, checked and written back with its update time.
+fn add_key_v0(old: &mut DIDV0, did: &String, pk: Vec<u8>) {
+    assert!(old.status.get(did) == Some(Status::VALID));
+    let mut public_key_list = old.public_key.get(did).unwrap();
+    assert!(public_key_list
+        .public_key_list
+        .iter()
+        .all(|x| x.public_key() != &pk));
+    public_key_list
+        .public_key_list
+        .push(PublicKey::new_pk(did, pk));
+    old.public_key.insert(did, &public_key_list);
+    old.updated.insert(did, &env::block_timestamp());
+}
+
+/// write the contract state of the first layout with a valid DID and a deactivated one
+fn write_state_v0() {
+    let mut old = DIDV0 {
+        status: UnorderedMap::new(b"s".to_vec()),
+        contexts: UnorderedMap::new(b"c".to_vec()),
+        public_key: UnorderedMap::new(b"p".to_vec()),
+        authentication: UnorderedMap::new(b"a".to_vec()),
+        controller: UnorderedMap::new(b"r".to_vec()),
+        service: UnorderedMap::new(b"v".to_vec()),
+        created: UnorderedMap::new(b"t".to_vec()),
+        updated: UnorderedMap::new(b"u".to_vec()),
+    };
+    let did = "did:near:bob_near".to_string();
+    let controller = "did:near:abcde.testnet".to_string();
+    old.status.insert(&did, &Status::VALID);
+    old.contexts
+        .insert(&did, &vec!["https://example.com/v1".to_string()]);
+    let public_key_list = PublicKeyListV0 {
+        public_key_list: vec![
+            PublicKey::new_pk_and_auth(&did, default_pk()),
+            PublicKey::new_auth(&controller, vec![0, 2]),
+        ],
+    };
+    old.public_key.insert(&did, &public_key_list);
+    old.authentication.insert(&did, &vec![0, 1]);
+    old.controller.insert(&did, &vec![controller.clone()]);
+    let service = ServiceV0 {
+        id: "hub".to_string(),
+        tp: "IdentityHub".to_string(),
+        service_endpoint: "https://hub.example.com".to_string(),
+    };
+    old.service.insert(&did, &vec![service]);
+    old.created.insert(&did, &10);
+    old.updated.insert(&did, &20);
+    old.status
+        .insert(&"did:near:carol_near".to_string(), &Status::DEACTIVATED);
+    env::state_write(&old);
+}
+
+/// the Borsh layout of the stored values of layout 1, a change of it requires a new `STATE_VERSION`.
+#[test]
+fn state_layout_test() {
+    let mut expected = vec![0u8]; // status
+    expected.extend(&0u32.to_le_bytes()); // contexts
+    expected.extend(&0u32.to_le_bytes()); // key_count
+    expected.extend(&0u32.to_le_bytes()); // authentication
+    expected.extend(&0u32.to_le_bytes()); // controllers
+    expected.extend(&0u32.to_le_bytes()); // services
+    expected.extend(&7u64.to_le_bytes()); // created
+    expected.extend(&0u64.to_le_bytes()); // updated
+    expected.extend(&0u64.to_le_bytes()); // version
+    expected.push(0); // auth_mode
+    expected.push(0); // bound_account
+    expected.extend(&0u32.to_le_bytes()); // also_known_as
+    assert_eq!(DidRecord::new(7).try_to_vec().unwrap(), expected);
+
+    let limits = Limits::default();
+    let expected: Vec<u8> = [32u32, 32, 16, 16, 66, 128, 256, 4096, 16]
+        .iter()
+        .flat_map(|x| x.to_le_bytes().to_vec())
+        .collect();
+    assert_eq!(limits.try_to_vec().unwrap(), expected);
+
+    let staged_code = StagedCode {
+        code_hash: vec![1],
+        staged_by: "a".to_string(),
+        staged_at: 2,
+        deployable_at: 3,
+        approvals: vec![],
+    };
+    let mut expected = vec![1, 0, 0, 0, 1, 1, 0, 0, 0, b'a'];
+    expected.extend(&2u64.to_le_bytes());
+    expected.extend(&3u64.to_le_bytes());
+    expected.extend(&0u32.to_le_bytes());
+    assert_eq!(staged_code.try_to_vec().unwrap(), expected);
+}
+
+#[test]
+fn migrate_test() {
+    let mut context = get_context("bob_near".to_string(), vec![], false);
+    context.predecessor_account_id = "alice_near".to_string();
+    testing_env!(context);
+    write_state_v0();
+    let mut contract = DID::migrate(Some("dave_near".to_string()), None);
+    assert_eq!(contract.state_version, STATE_VERSION);
+    assert_eq!(contract.get_roles().owner_id, "dave_near");
+    assert_eq!(contract.get_did_count(), 0);
+
+    let did = "did:near:bob_near".to_string();
+    let controller = "did:near:abcde.testnet".to_string();
+    // the mutations wait for the DIDs to be migrated
+    assert_panics_with(
+        || contract.add_key(vec![0, 3], controller.clone()),
+        "add_key, migration is in progress",
+    );
+
+    // the DIDs are migrated by the owner in batches, then the statuses of the first layout are removed
+    let mut context = get_context("bob_near".to_string(), vec![], false);
+    context.predecessor_account_id = "dave_near".to_string();
+    switch_context(context);
+    assert_eq!(contract.migrate_dids(1), 3);
+    assert_eq!(contract.get_dids(0, 10), vec![did.clone()]);
+    assert_eq!(contract.migrate_dids(2), 1);
+    assert_eq!(contract.migrate_dids(10), 0);
+    assert!(!env::storage_has_key(b"STATE_V0"));
+
+    // the DIDs keep their registration order, the deactivated DID of the first layout has no creation time
+    assert_eq!(
+        contract.get_dids(0, 10),
+        vec![did.clone(), "did:near:carol_near".to_string()]
+    );
+    assert_eq!(contract.get_dids_registered_after(9, 0, 10).len(), 2);
+    assert!(contract.get_dids_registered_after(10, 0, 10).is_empty());
+    assert!(contract
+        .get_document("did:near:carol_near".to_string())
+        .is_none());
+    assert_eq!(contract.get_version_id(did.clone()), 1);
+    let doc = contract.get_document(did.clone()).unwrap();
+    assert!(doc.contains("https://example.com/v1"));
+    assert!(doc.contains("\"did:near:bob_near#keys-2\""));
+    assert!(doc.contains("\"id\":\"did:near:bob_near#hub\""));
+    assert!(doc.contains("\"type\":\"IdentityHub\""));
+    assert!(doc.contains("\"created\":10"));
+    assert!(doc.contains("\"updated\":20"));
+    assert_eq!(
+        contract.get_operations(did.clone(), 0, 10)[0].kind,
+        "migrate"
+    );
+    assert_eq!(contract.find_dids_by_key(vec![0, 2], None).len(), 1);
+    assert_eq!(
+        contract.get_controlled_dids(controller.clone(), 0, 10),
+        vec![did.clone()]
+    );
+    // the migrated DID has a storage account with no balance, its existing bytes are paid by the contract
+    let balance = contract.storage_balance_of("bob_near".to_string()).unwrap();
+    assert_eq!(balance.total.0, 0);
+
+    // the migrated DID can be updated with its keys
+    let context = get_context("bob_near".to_string(), vec![], false);
+    switch_context(context);
+    contract.add_key(vec![0, 3], controller.clone());
+    assert_eq!(contract.get_version_id(did.clone()), 2);
+    contract.verify_signature();
+}
+
+#[test]
+fn migrate_twice_test() {
+    let mut context = get_context("bob_near".to_string(), vec![], false);
+    context.predecessor_account_id = "alice_near".to_string();
+    testing_env!(context);
+    write_state_v0();
+    let mut contract = DID::migrate(Some("alice_near".to_string()), None);
+    contract.migrate_dids(10);
+    env::state_write(&contract);
+    // the state is up to date, it's kept with its owner and config
+    let contract = DID::migrate(None, None);
+    assert_eq!(contract.get_did_count(), 2);
+    assert_eq!(contract.get_version_id("did:near:bob_near".to_string()), 1);
+    assert_panics_with(
+        || DID::migrate(Some("dave_near".to_string()), None),
+        "migrate, owner and config of an up to date state are kept",
+    );
+}
+
+#[test]
+#[should_panic(expected = "migrate, owner_id is required to migrate the state of version 0")]
+fn migrate_without_owner_test() {
+    let mut context = get_context("bob_near".to_string(), vec![], false);
+    context.predecessor_account_id = "alice_near".to_string();
+    testing_env!(context);
+    write_state_v0();
+    DID::migrate(None, None);
+}
+
+#[test]
+fn roles_test() {
+    let mut context = get_context("bob_near".to_string(), vec![], false);
+    context.predecessor_account_id = "alice_near".to_string();
+    testing_env!(context);
+    let mut contract = DID::new("alice_near".to_string(), None);
+    assert_eq!(contract.get_roles().owner_id, "alice_near");
+    contract.add_admin("bob_near".to_string());
+    contract.add_operator("dave_near".to_string());
+
+    // an admin can manage the operators and the configuration
+    let mut context = get_context("bob_near".to_string(), vec![], false);
+    context.predecessor_account_id = "bob_near".to_string();
+    switch_context(context);
+    contract.remove_operator("dave_near".to_string());
+    contract.set_service_types(vec!["LinkedDomains".to_string()]);
+    assert!(contract.get_roles().operators.is_empty());
+
+    let mut context = get_context("bob_near".to_string(), vec![], false);
+    context.predecessor_account_id = "alice_near".to_string();
+    switch_context(context);
+    contract.transfer_ownership("bob_near".to_string());
+    let roles = contract.get_roles();
+    assert_eq!(roles.owner_id, "bob_near");
+    assert_eq!(roles.admins, vec!["bob_near".to_string()]);
+}
+
+#[test]
+#[should_panic(expected = "only the owner or admins can invoke this method")]
+fn admin_only_test() {
+    let context = get_context("bob_near".to_string(), vec![], false);
+    testing_env!(context);
+    let mut contract = DID::new("alice_near".to_string(), None);
+    contract.set_limits(Limits::default());
+}
+
+#[test]
+fn pause_test() {
+    let mut context = get_context("bob_near".to_string(), vec![], false);
+    context.predecessor_account_id = "alice_near".to_string();
+    testing_env!(context);
+    let mut contract = DID::new("alice_near".to_string(), None);
+    contract.reg_did_using_account();
+    contract.add_operator("dave_near".to_string());
+    let did = "did:near:bob_near".to_string();
+
+    let mut context = get_context("bob_near".to_string(), vec![], false);
+    context.predecessor_account_id = "dave_near".to_string();
+    switch_context(context);
+    contract.pause(Some(vec![PauseGroup::Keys]));
+    assert_eq!(contract.get_paused(), vec![PauseGroup::Keys]);
+    // the other groups are not paused
+    contract.add_context(vec!["https://example.com/v1".to_string()]);
+
+    contract.pause(None);
+    assert_eq!(contract.get_paused().len(), PauseGroup::all().len());
+    assert!(contract.get_document(did.clone()).is_some());
+    contract.verify_signature();
+
+    let mut context = get_context("bob_near".to_string(), vec![], false);
+    context.predecessor_account_id = "alice_near".to_string();
+    switch_context(context);
+    contract.unpause(None);
+    assert!(contract.get_paused().is_empty());
+    contract.add_key(vec![0, 1], did.clone());
+}
+
+#[test]
+#[should_panic(expected = "add_key, keys is paused")]
+fn paused_mutation_test() {
+    let mut context = get_context("bob_near".to_string(), vec![], false);
+    context.predecessor_account_id = "alice_near".to_string();
+    testing_env!(context);
+    let mut contract = DID::new("alice_near".to_string(), None);
+    contract.reg_did_using_account();
+    contract.pause(Some(vec![PauseGroup::Keys]));
+    contract.add_key(vec![0, 1], "did:near:bob_near".to_string());
+}
+
+#[test]
+fn upgrade_test() {
+    let code = vec![0u8, 97, 115, 109, 1, 0, 0, 0];
+    let mut context = get_context("bob_near".to_string(), code.clone(), false);
+    context.predecessor_account_id = "alice_near".to_string();
+    testing_env!(context);
+    let mut contract = DID::new("alice_near".to_string(), None);
+    contract.add_admin("bob_near".to_string());
+    contract.add_admin("dave_near".to_string());
+    contract.stage_code();
+    let staged = contract.get_staged_code().unwrap();
+    assert_eq!(staged.code_hash, env::sha256(&code).to_base58());
+    assert_eq!(staged.deployable_at, contract.get_upgrade_config().timelock);
+
+    // two admins approve the code instead of the owner
+    for admin in ["bob_near", "dave_near"].iter() {
+        let mut context = get_context("bob_near".to_string(), vec![], false);
+        context.predecessor_account_id = admin.to_string();
+        switch_context(context);
+        contract.approve_code(staged.code_hash.clone());
+    }
+
+    let mut context = get_context("bob_near".to_string(), vec![], false);
+    context.predecessor_account_id = "bob_near".to_string();
+    context.block_timestamp = staged.deployable_at;
+    switch_context(context);
+    contract.deploy_staged_code();
+    assert!(contract.get_staged_code().is_none());
+}
+
+#[test]
+#[should_panic(expected = "deploy_staged_code, timelock has not passed")]
+fn upgrade_timelock_test() {
+    let mut context = get_context("bob_near".to_string(), vec![0u8, 97, 115, 109], false);
+    context.predecessor_account_id = "alice_near".to_string();
+    testing_env!(context);
+    let mut contract = DID::new("alice_near".to_string(), None);
+    contract.stage_code();
+    let code_hash = contract.get_staged_code().unwrap().code_hash;
+    contract.approve_code(code_hash);
+    contract.deploy_staged_code();
+}
+
+#[test]
+#[should_panic(expected = "deploy_staged_code, timelock has not passed")]
+fn upgrade_timelock_config_test() {
+    let mut context = get_context("bob_near".to_string(), vec![0u8, 97, 115, 109], false);
+    context.predecessor_account_id = "alice_near".to_string();
+    testing_env!(context);
+    let mut contract = DID::new("alice_near".to_string(), None);
+    contract.stage_code();
+    let code_hash = contract.get_staged_code().unwrap().code_hash;
+    contract.approve_code(code_hash);
+
+    // the staged code keeps the timelock it was staged with
+    contract.set_upgrade_config(UpgradeConfig {
+        timelock: 0,
+        threshold: 2,
+    });
+    assert_eq!(
+        contract.get_staged_code().unwrap().deployable_at,
+        UpgradeConfig::default().timelock
+    );
+    contract.deploy_staged_code();
+}
+
+#[test]
+#[should_panic(expected = "stage_code, timelock is too long")]
+fn upgrade_timelock_overflow_test() {
+    let mut context = get_context("bob_near".to_string(), vec![0u8, 97, 115, 109], false);
+    context.predecessor_account_id = "alice_near".to_string();
+    context.block_timestamp = 1;
+    testing_env!(context);
+    let mut contract = DID::new("alice_near".to_string(), None);
+    contract.set_upgrade_config(UpgradeConfig {
+        timelock: u64::MAX,
+        threshold: 2,
+    });
+    contract.stage_code();
+}
+
+#[test]
+fn set_contexts_test() {
+    let context = get_context("bob_near".to_string(), vec![], false);
+    testing_env!(context);
+    let mut contract = DID::new("alice_near".to_string(), None);
+    contract.reg_did_using_account();
+    let did = "did:near:bob_near".to_string();
+    let c1 = "https://example.com/c1".to_string();
+    let c2 = "https://example.com/c2".to_string();
+    let c3 = "https://example.com/c3".to_string();
+    contract.add_context(vec![c1.clone(), c2.clone(), c3.clone()]);
+    contract.remove_context(vec![c2.clone()]);
+    let doc = contract.get_document(did.clone()).unwrap();
+    let expected = format!(
+        "\"@contexts\":[\"{}\",\"{}\",\"{}\",\"{}\"]",
+        DEFAULT_CONTEXT1, DEFAULT_CONTEXT2, c1, c3
+    );
+    assert!(doc.contains(&expected));
+
+    contract.set_contexts(vec![c3.clone(), DEFAULT_CONTEXT1.to_string(), c2.clone()]);
+    let doc = contract.get_document(did.clone()).unwrap();
+    let expected = format!(
+        "\"@contexts\":[\"{}\",\"{}\",\"{}\",\"{}\"]",
+        DEFAULT_CONTEXT1, DEFAULT_CONTEXT2, c3, c2
+    );
+    assert!(doc.contains(&expected));
+}
+
+#[test]
+#[should_panic(expected = "is not allowed")]
+fn context_allowlist_test() {
+    let mut context = get_context("bob_near".to_string(), vec![], false);
+    context.predecessor_account_id = "alice_near".to_string();
+    testing_env!(context);
+    let mut contract = DID::new("alice_near".to_string(), None);
+    contract.reg_did_using_account();
+    contract.set_context_allowlist(vec!["https://example.com/c1".to_string()]);
+    contract.add_context(vec!["https://example.com/c1".to_string()]);
+    contract.add_context(vec!["https://example.com/c2".to_string()]);
+}
+
+#[test]
+fn integrity_test() {
+    let mut context = get_context("bob_near".to_string(), vec![], false);
+    context.predecessor_account_id = "alice_near".to_string();
+    testing_env!(context);
+    let mut contract = DID::new("alice_near".to_string(), None);
+    contract.reg_did_using_account();
+    let did = "did:near:bob_near".to_string();
+    contract.add_new_auth_key(vec![0, 1], did.clone());
+    contract.add_controller("did:near:abcde.testnet".to_string());
+
+    // the deactivated key is removed from the authentication list
+    contract.deactivate_key(vec![0, 1]);
+    assert!(contract.check_integrity(did.clone()).is_empty());
+    let doc = contract.get_document(did.clone()).unwrap();
+    assert!(!doc.contains("did:near:bob_near#keys-2"));
+
+    // the state of the previous versions could reference a deactivated key
+    contract.corrupt_record(&did, |record| record.authentication.push(1));
+    contract
+        .controlled
+        .remove(&"did:near:abcde.testnet".to_string());
+    assert_eq!(
+        contract.check_integrity(did.clone()),
+        vec![
+            "authentication key 1 is deactivated".to_string(),
+            "controller did:near:abcde.testnet is not indexed".to_string(),
+        ]
+    );
+    contract.repair_did(did.clone());
+    assert!(contract.check_integrity(did.clone()).is_empty());
+    assert_eq!(
+        contract.get_operations(did.clone(), 4, 1)[0].kind,
+        "repair_did"
+    );
+}
+
+#[test]
+#[should_panic(expected = "reg_did_using_account, did exists")]
+fn reg_did_twice_test() {
+    let context = get_context("bob_near".to_string(), vec![], false);
+    testing_env!(context);
+    let mut contract = DID::new("alice_near".to_string(), None);
+    contract.reg_did_using_account();
+    contract.reg_did_using_account();
+}
+
+#[test]
+#[should_panic(expected = "did doesn't exist")]
+fn unregistered_did_test() {
+    let context = get_context("bob_near".to_string(), vec![], false);
+    testing_env!(context);
+    let mut contract = DID::new("alice_near".to_string(), None);
+    contract.add_context(vec!["https://example.com/v1".to_string()]);
+}
+
+#[test]
+#[should_panic(expected = "did status is not valid")]
+fn deactivate_did_twice_test() {
+    let context = get_context("bob_near".to_string(), vec![], false);
+    testing_env!(context);
+    let mut contract = DID::new("alice_near".to_string(), None);
+    contract.reg_did_using_account();
+    contract.deactivate_did();
+    contract.deactivate_did();
+}
+
+#[test]
+#[should_panic(expected = "remove_controller, controller doesn't exist")]
+fn remove_missing_controller_test() {
+    let context = get_context("bob_near".to_string(), vec![], false);
+    testing_env!(context);
+    let mut contract = DID::new("alice_near".to_string(), None);
+    contract.reg_did_using_account();
+    contract.remove_controller("did:near:abcde.testnet".to_string());
+}
+
+#[test]
+#[should_panic(expected = "check_did, invalid DID")]
+fn short_controller_test() {
+    let context = get_context("bob_near".to_string(), vec![], false);
+    testing_env!(context);
+    let mut contract = DID::new("alice_near".to_string(), None);
+    contract.reg_did_using_account();
+    contract.add_controller("did".to_string());
+}
+
+#[test]
+#[should_panic(expected = "add_key, public key is empty")]
+fn empty_key_test() {
+    let context = get_context("bob_near".to_string(), vec![], false);
+    testing_env!(context);
+    let mut contract = DID::new("alice_near".to_string(), None);
+    contract.reg_did_using_account();
+    // an empty key was stored before, then the document couldn't be rendered
+    contract.add_key(vec![], "did:near:bob_near".to_string());
+}
+
+// the state machine tests below drive the contract and a reference model with the same steps,
+// a step must fail with the same message on both, and the documents must be the same after every step.
+
+const ACCOUNTS: &[&str] = &["bob_near", "carol_near", "dave_near"];
+const CONTROLLERS: &[&str] = &[
+    "did:near:bob_near",
+    "did:near:carol_near",
+    "did:near:dave_near",
+    "did:near:",
+    "near:bob",
+];
+const SERVICE_IDS: &[&str] = &["s1", "s2", "bad id"];
+const ENDPOINTS: &[&str] = &[
+    "https://a.example.com",
+    "did:near:carol_near",
+    "ftp://example.com",
+];
+const CONTEXTS: &[&str] = &[
+    "https://example.com/v1",
+    "https://example.org/v2",
+    DEFAULT_CONTEXT1,
+    "ftp://example.com",
+    "example",
+];
+const SERVICE_TYPE: &str = "LinkedDomains";
+
+fn gen_did(account_id: &str) -> String {
+    Config::default().gen_did(account_id)
+}
+
+fn key_pool() -> Vec<Vec<u8>> {
+    vec![default_pk(), vec![0, 1], vec![1, 2, 3], vec![2, 9], vec![]]
+}
+
+/// a xorshift generator, the generated steps are reproducible from the seed.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn chance(&mut self, percent: u64) -> bool {
+        self.next() % 100 < percent
+    }
+
+    fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Op {
+    Reg,
+    DeactivateDid,
+    AddController(String),
+    RemoveController(String),
+    AddKey(Vec<u8>, String),
+    AddNewAuthKey(Vec<u8>, String),
+    DeactivateKey(Vec<u8>),
+    SetAuthKey(Vec<u8>),
+    DeactivateAuthKey(Vec<u8>),
+    AddNewAuthKeyByController(String, Vec<u8>, String),
+    SetAuthKeyByController(String, Vec<u8>),
+    DeactivateAuthKeyByController(String, Vec<u8>),
+    AddService(String, String),
+    UpdateService(String, String),
+    RemoveService(String),
+    AddContext(Vec<String>),
+    RemoveContext(Vec<String>),
+    SetContexts(Vec<String>),
+}
+
+impl Op {
+    fn apply(&self, contract: &mut DID) {
+        match self.clone() {
+            Op::Reg => contract.reg_did_using_account(),
+            Op::DeactivateDid => contract.deactivate_did(),
+            Op::AddController(controller) => contract.add_controller(controller),
+            Op::RemoveController(controller) => contract.remove_controller(controller),
+            Op::AddKey(pk, controller) => contract.add_key(pk, controller),
+            Op::AddNewAuthKey(pk, controller) => contract.add_new_auth_key(pk, controller),
+            Op::DeactivateKey(pk) => contract.deactivate_key(pk),
+            Op::SetAuthKey(pk) => contract.set_auth_key(pk),
+            Op::DeactivateAuthKey(pk) => contract.deactivate_auth_key(pk),
+            Op::AddNewAuthKeyByController(did, pk, controller) => {
+                contract.add_new_auth_key_by_controller(did, pk, controller)
+            }
+            Op::SetAuthKeyByController(did, pk) => contract.set_auth_key_by_controller(did, pk),
+            Op::DeactivateAuthKeyByController(did, pk) => {
+                contract.deactivate_auth_key_by_controller(did, pk)
+            }
+            Op::AddService(id, endpoint) => contract.add_service(
+                id,
+                ServiceType::Type(SERVICE_TYPE.to_string()),
+                ServiceEndpoint::Uri(endpoint),
+                None,
+            ),
+            Op::UpdateService(id, endpoint) => contract.update_service(
+                id,
+                ServiceType::Type(SERVICE_TYPE.to_string()),
+                ServiceEndpoint::Uri(endpoint),
+                None,
+            ),
+            Op::RemoveService(id) => contract.remove_service(id),
+            Op::AddContext(contexts) => contract.add_context(contexts),
+            Op::RemoveContext(contexts) => contract.remove_context(contexts),
+            Op::SetContexts(contexts) => contract.set_contexts(contexts),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Step {
+    signer: String,
+    signer_pk: Vec<u8>,
+    op: Op,
+}
+
+fn step(signer: &str, signer_pk: Vec<u8>, op: Op) -> Step {
+    Step {
+        signer: signer.to_string(),
+        signer_pk,
+        op,
+    }
+}
+
+#[derive(Clone)]
+struct ModelKey {
+    pk: Vec<u8>,
+    controller: String,
+    pk_list: bool,
+    auth: bool,
+    deactivated: bool,
+}
+
+#[derive(Clone)]
+struct ModelDid {
+    valid: bool,
+    keys: Vec<ModelKey>,
+    authentication: Vec<usize>,
+    controllers: Vec<String>,
+    services: Vec<(String, String)>,
+    contexts: Vec<String>,
+    created: u64,
+    updated: u64,
+    version: u64,
+}
+
+impl ModelDid {
+    fn find(&self, pk: &Vec<u8>) -> Option<usize> {
+        self.keys.iter().position(|k| &k.pk == pk)
+    }
+
+    fn access(&self, pk: &Vec<u8>) -> Result<(), String> {
+        match self.find(pk).map(|i| &self.keys[i]) {
+            None => fail("check_pk_access, pk doesn't exist"),
+            Some(k) if k.deactivated => fail("check_pk_access, pk is deactivated"),
+            Some(k) if !k.auth => fail("check_pk_access, pk is not authentication"),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_controller(&self, method: &str, controller_did: &String) -> Result<(), String> {
+        if !self.controllers.contains(controller_did) {
+            return Err(format!("{}, signer is not controller", method));
+        }
+        Ok(())
+    }
+
+    fn push_key(
+        &mut self,
+        method: &str,
+        pk: &Vec<u8>,
+        controller: &String,
+        pk_list: bool,
+        auth: bool,
+    ) -> Result<(), String> {
+        if self.find(pk).is_some() {
+            return Err(format!("{}, pk exists", method));
+        }
+        if pk.is_empty() {
+            return Err(format!("{}, public key is empty", method));
+        }
+        self.keys.push(ModelKey {
+            pk: pk.clone(),
+            controller: controller.clone(),
+            pk_list,
+            auth,
+            deactivated: false,
+        });
+        if auth {
+            self.authentication.push(self.keys.len() - 1);
+        }
+        Ok(())
+    }
+
+    fn set_auth(&mut self, pk: &Vec<u8>) -> Result<(), String> {
+        let i = match self.find(pk) {
+            Some(v) => v,
+            None => return fail("set_pk_auth, pk doesn't exist"),
+        };
+        if self.keys[i].deactivated {
+            return fail("set_pk_auth, pk is deactivated");
+        }
+        if self.keys[i].auth {
+            return fail("set_pk_auth, pk is already auth key");
+        }
+        self.keys[i].auth = true;
+        self.authentication.push(i);
+        Ok(())
+    }
+
+    fn remove_auth(&mut self, pk: &Vec<u8>) -> Result<(), String> {
+        let i = match self.find(pk) {
+            Some(v) => v,
+            None => return fail("remove_pk_auth, pk doesn't exist"),
+        };
+        if self.keys[i].deactivated {
+            return fail("remove_pk_auth, pk is deactivated");
+        }
+        if !self.keys[i].auth {
+            return fail("remove_pk_auth, pk is not auth key");
+        }
+        self.keys[i].auth = false;
+        self.authentication.retain(|x| *x != i);
+        Ok(())
+    }
+
+    fn render(&self, did: &str) -> Option<serde_json::Value> {
+        if !self.valid {
+            return None;
+        }
+        let key_json = |i: usize| {
+            let k = &self.keys[i];
+            let tp = match k.pk[0] {
+                0 => "Ed25519VerificationKey2018",
+                1 => "EcdsaSecp256k1VerificationKey2019",
+                _ => "",
+            };
+            serde_json::json!({
+                "id": key_id(did, i as u32),
+                "type": tp,
+                "controller": k.controller,
+                "publicKeyBase58": k.pk.to_base58(),
+            })
+        };
+        let public_key: Vec<_> = (0..self.keys.len())
+            .filter(|i| self.keys[*i].pk_list)
+            .map(|i| key_json(i))
+            .collect();
+        let authentication: Vec<_> = self
+            .authentication
+            .iter()
+            .map(|i| {
+                if self.keys[*i].pk_list {
+                    serde_json::json!(key_id(did, *i as u32))
+                } else {
+                    key_json(*i)
+                }
+            })
+            .collect();
+        let mut contexts = vec![DEFAULT_CONTEXT1.to_string(), DEFAULT_CONTEXT2.to_string()];
+        contexts.extend(self.contexts.iter().cloned());
+        let service: Vec<_> = self
+            .services
+            .iter()
+            .map(|(id, endpoint)| {
+                serde_json::json!({
+                    "id": format!("{}#{}", did, id),
+                    "type": SERVICE_TYPE,
+                    "serviceEndpoint": endpoint,
+                })
+            })
+            .collect();
+        Some(serde_json::json!({
+            "@contexts": contexts,
+            "id": did,
+            "publicKey": public_key,
+            "authentication": authentication,
+            "controller": self.controllers,
+            "service": service,
+            "created": self.created,
+            "updated": self.updated,
+        }))
+    }
+}
+
+fn fail<T>(message: &str) -> Result<T, String> {
+    Err(message.to_string())
+}
+
+fn model_check_did(did: &str) -> Result<(), String> {
+    if !did.starts_with("did:near:") || did.len() == "did:near:".len() {
+        return fail("check_did, invalid DID");
+    }
+    Ok(())
+}
+
+fn model_check_service(method: &str, id: &str, endpoint: &str) -> Result<(), String> {
+    if id.contains(' ') {
+        return Err(format!("{}, invalid character ' ' in \"{}\"", method, id));
+    }
+    if endpoint.starts_with("ftp:") {
+        return Err(format!("{}, scheme \"ftp\" is not allowed", method));
+    }
+    Ok(())
+}
+
+fn model_check_contexts(
+    method: &str,
+    existing: Vec<String>,
+    contexts: &Vec<String>,
+) -> Result<Vec<String>, String> {
+    let mut result = existing;
+    for v in contexts {
+        if result.contains(v) || v == DEFAULT_CONTEXT1 || v == DEFAULT_CONTEXT2 {
+            continue;
+        }
+        if v.starts_with("ftp:") {
+            return Err(format!("{}, scheme \"ftp\" is not allowed", method));
+        }
+        if !v.contains(':') {
+            return Err(format!("{}, \"{}\" is not an absolute URI", method, v));
+        }
+        result.push(v.clone());
+    }
+    Ok(result)
+}
+
+/// the reference model of the contract, the DIDs are kept as plain lists without indexes or versions.
+#[derive(Clone, Default)]
+struct Model {
+    dids: BTreeMap<String, ModelDid>,
+}
+
+impl Model {
+    fn valid(&self, did: &String) -> Result<ModelDid, String> {
+        match self.dids.get(did) {
+            None => fail("did doesn't exist"),
+            Some(d) if !d.valid => fail("did status is not valid"),
+            Some(d) => Ok(d.clone()),
+        }
+    }
+
+    /// the keys which the account can sign with.
+    fn usable_keys(&self, did: &String) -> Vec<Vec<u8>> {
+        match self.dids.get(did) {
+            Some(d) if d.valid => d
+                .keys
+                .iter()
+                .filter(|k| k.auth && !k.deactivated)
+                .map(|k| k.pk.clone())
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    fn apply(&mut self, step: &Step, timestamp: u64) -> Result<(), String> {
+        let did = gen_did(&step.signer);
+        let signer_pk = &step.signer_pk;
+        let (target, mut d) = match &step.op {
+            Op::Reg => {
+                if self.dids.contains_key(&did) {
+                    return fail("reg_did_using_account, did exists");
+                }
+                let d = ModelDid {
+                    valid: true,
+                    keys: vec![ModelKey {
+                        pk: signer_pk.clone(),
+                        controller: did.clone(),
+                        pk_list: true,
+                        auth: true,
+                        deactivated: false,
+                    }],
+                    authentication: vec![0],
+                    controllers: vec![],
+                    services: vec![],
+                    contexts: vec![],
+                    created: timestamp,
+                    updated: 0,
+                    version: 1,
+                };
+                self.dids.insert(did, d);
+                return Ok(());
+            }
+            Op::AddNewAuthKeyByController(target, pk, controller) => {
+                let method = "add_new_auth_key_by_controller";
+                let mut d = self.valid(target)?;
+                let c = self.valid(&did)?;
+                model_check_did(controller)?;
+                d.check_controller(method, &did)?;
+                c.access(signer_pk)?;
+                d.push_key(method, pk, controller, false, true)?;
+                (target.clone(), d)
+            }
+            Op::SetAuthKeyByController(target, pk) => {
+                let mut d = self.valid(target)?;
+                let c = self.valid(&did)?;
+                d.check_controller("set_auth_key_by_controller", &did)?;
+                c.access(signer_pk)?;
+                d.set_auth(pk)?;
+                (target.clone(), d)
+            }
+            Op::DeactivateAuthKeyByController(target, pk) => {
+                let mut d = self.valid(target)?;
+                let c = self.valid(&did)?;
+                d.check_controller("deactivate_auth_key_by_controller", &did)?;
+                c.access(signer_pk)?;
+                d.remove_auth(pk)?;
+                (target.clone(), d)
+            }
+            op => {
+                let mut d = self.valid(&did)?;
+                Model::apply_owner(&mut d, signer_pk, op)?;
+                (did, d)
+            }
+        };
+        d.version += 1;
+        d.updated = timestamp;
+        self.dids.insert(target, d);
+        Ok(())
+    }
+
+    /// apply the methods invoked by the DID owner, the checks are done in the same order as the contract.
+    fn apply_owner(d: &mut ModelDid, signer_pk: &Vec<u8>, op: &Op) -> Result<(), String> {
+        match op {
+            Op::AddKey(pk, controller) => {
+                model_check_did(controller)?;
+                d.access(signer_pk)?;
+                return d.push_key("add_key", pk, controller, true, false);
+            }
+            Op::AddNewAuthKey(pk, controller) => {
+                model_check_did(controller)?;
+                d.access(signer_pk)?;
+                return d.push_key("add_new_auth_key", pk, controller, false, true);
+            }
+            _ => d.access(signer_pk)?,
+        }
+        match op {
+            Op::DeactivateDid => {
+                d.valid = false;
+                d.authentication = vec![];
+                d.controllers = vec![];
+                d.services = vec![];
+                d.contexts = vec![];
+            }
+            Op::AddController(controller) => {
+                model_check_did(controller)?;
+                if d.controllers.contains(controller) {
+                    return fail("add_controller, controller exists");
+                }
+                d.controllers.push(controller.clone());
+            }
+            Op::RemoveController(controller) => {
+                match d.controllers.iter().position(|x| x == controller) {
+                    Some(i) => d.controllers.remove(i),
+                    None => return fail("remove_controller, controller doesn't exist"),
+                };
+            }
+            Op::DeactivateKey(pk) => {
+                let i = match d.find(pk) {
+                    Some(v) => v,
+                    None => return fail("deactivate_pk, pk doesn't exist"),
+                };
+                if d.keys[i].deactivated {
+                    return fail("deactivate_pk, pk is deactivated");
+                }
+                d.keys[i].deactivated = true;
+                d.authentication.retain(|x| *x != i);
+            }
+            Op::SetAuthKey(pk) => d.set_auth(pk)?,
+            Op::DeactivateAuthKey(pk) => d.remove_auth(pk)?,
+            Op::AddService(id, endpoint) => {
+                model_check_service("add_service", id, endpoint)?;
+                if d.services.iter().any(|(x, _)| x == id) {
+                    return fail("add_service, service exists");
+                }
+                d.services.push((id.clone(), endpoint.clone()));
+            }
+            Op::UpdateService(id, endpoint) => {
+                let i = match d.services.iter().position(|(x, _)| x == id) {
+                    Some(v) => v,
+                    None => return fail("update_service, service doesn't exist"),
+                };
+                model_check_service("update_service", id, endpoint)?;
+                d.services[i].1 = endpoint.clone();
+            }
+            Op::RemoveService(id) => match d.services.iter().position(|(x, _)| x == id) {
+                Some(i) => {
+                    d.services.remove(i);
+                }
+                None => return fail("remove_service, service doesn't exist"),
+            },
+            Op::AddContext(contexts) => {
+                d.contexts = model_check_contexts("add_context", d.contexts.clone(), contexts)?
+            }
+            Op::RemoveContext(contexts) => {
+                for v in contexts {
+                    match d.contexts.iter().position(|x| x == v) {
+                        Some(i) => d.contexts.remove(i),
+                        None => return fail("remove_context, context doesn't exist"),
+                    };
+                }
+            }
+            Op::SetContexts(contexts) => {
+                d.contexts = model_check_contexts("set_contexts", vec![], contexts)?
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+}
+
+fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+    if let Some(v) = payload.downcast_ref::<String>() {
+        return v.clone();
+    }
+    payload
+        .downcast_ref::<&str>()
+        .map(|v| v.to_string())
+        .unwrap_or_default()
+}
+
+/// run `f` and check it panics with a message containing `expected`.
+fn assert_panics_with<R>(f: impl FnOnce() -> R, expected: &str) {
+    let payload = match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(_) => panic!("{}: no panic", expected),
+        Err(v) => v,
+    };
+    // the message is debug formatted by the mocked blockchain
+    let message = panic_message(payload);
+    let expected_debug = format!("{:?}", expected);
+    assert!(
+        message.contains(expected)
+            || message.contains(&expected_debug[1..expected_debug.len() - 1]),
+        "{}: {}",
+        expected,
+        message
+    );
+}
+
+/// run the step on the contract and the model, the state of both is reverted if the step fails, as the chain does.
+fn run_step(
+    contract: &mut DID,
+    model: &mut Model,
+    step: &Step,
+    timestamp: u64,
+) -> Result<(), String> {
+    let mut context =
+        get_context_with_pk(step.signer.clone(), step.signer_pk.clone(), vec![], false);
+    context.block_timestamp = timestamp;
+    context.storage_usage = env::storage_usage();
+    let storage = take_storage();
+    let state = contract.try_to_vec().unwrap();
+    set_blockchain(context.clone(), storage.clone());
+
+    let mut next = model.clone();
+    let expected = next.apply(step, timestamp);
+    let result = catch_unwind(AssertUnwindSafe(|| step.op.apply(contract)));
+    match (&expected, result) {
+        (Ok(()), Ok(())) => *model = next,
+        (Err(e), Err(payload)) => {
+            // the message is debug formatted by the mocked blockchain
+            let message = panic_message(payload);
+            let e_debug = format!("{:?}", e);
+            assert!(
+                message.contains(e.as_str()) || message.contains(&e_debug[1..e_debug.len() - 1]),
+                "{:?}: expected \"{}\", got \"{}\"",
+                step,
+                e,
+                message
+            );
+            set_blockchain(context, storage);
+            *contract = DID::try_from_slice(&state).unwrap();
+        }
+        (Ok(()), Err(payload)) => panic!(
+            "{:?}: unexpected failure \"{}\"",
+            step,
+            panic_message(payload)
+        ),
+        (Err(e), Ok(())) => panic!("{:?}: expected failure \"{}\"", step, e),
+    }
+
+    for account in ACCOUNTS {
+        let did = gen_did(account);
+        let document = contract
+            .get_document(did.clone())
+            .map(|x| serde_json::from_str::<serde_json::Value>(&x).unwrap());
+        let model_did = model.dids.get(&did);
+        assert_eq!(
+            document,
+            model_did.and_then(|x| x.render(&did)),
+            "{:?}",
+            step
+        );
+        if let Some(d) = model_did {
+            assert_eq!(
+                contract.get_version_id(did.clone()),
+                d.version,
+                "{:?}",
+                step
+            );
+            assert!(
+                contract.check_integrity(did.clone()).is_empty(),
+                "{:?}",
+                step
+            );
+        }
+    }
+    expected
+}
+
+fn gen_step(rng: &mut Rng, model: &Model) -> Step {
+    let signer = rng.pick(ACCOUNTS).to_string();
+    let keys = key_pool();
+    let usable = model.usable_keys(&gen_did(&signer));
+    // the account signs with one of its authentication keys most of the time
+    let signer_pk = if !usable.is_empty() && rng.chance(80) {
+        rng.pick(&usable).clone()
+    } else {
+        rng.pick(&keys[..4]).clone()
+    };
+    let pk = rng.pick(&keys).clone();
+    let did = gen_did(rng.pick(ACCOUNTS));
+    let controller = rng.pick(CONTROLLERS).to_string();
+    let service_id = rng.pick(SERVICE_IDS).to_string();
+    let endpoint = rng.pick(ENDPOINTS).to_string();
+    let contexts: Vec<String> = (0..1 + rng.below(2))
+        .map(|_| rng.pick(CONTEXTS).to_string())
+        .collect();
+
+    let op = if !model.dids.contains_key(&gen_did(&signer)) && rng.chance(50) {
+        Op::Reg
+    } else {
+        match rng.below(18) {
+            // deactivation ends the DID, keep it rare
+            0 if rng.chance(20) => Op::DeactivateDid,
+            0 => Op::Reg,
+            1 => Op::AddController(controller),
+            2 => Op::RemoveController(controller),
+            3 => Op::AddKey(pk, controller),
+            4 => Op::AddNewAuthKey(pk, controller),
+            5 => Op::DeactivateKey(pk),
+            6 => Op::SetAuthKey(pk),
+            7 => Op::DeactivateAuthKey(pk),
+            8 => Op::AddNewAuthKeyByController(did, pk, controller),
+            9 => Op::SetAuthKeyByController(did, pk),
+            10 => Op::DeactivateAuthKeyByController(did, pk),
+            11 => Op::AddService(service_id, endpoint),
+            12 => Op::UpdateService(service_id, endpoint),
+            13 => Op::RemoveService(service_id),
+            14 => Op::AddContext(contexts),
+            15 => Op::RemoveContext(contexts),
+            _ => Op::SetContexts(contexts),
+        }
+    };
+    Step {
+        signer,
+        signer_pk,
+        op,
+    }
+}
+
+#[test]
+fn state_machine_test() {
+    for seed in 0..16 {
+        let context = get_context("bob_near".to_string(), vec![], false);
+        testing_env!(context);
+        let mut contract = DID::new("alice_near".to_string(), None);
+        let mut model = Model::default();
+        let mut rng = Rng::new(seed);
+        let mut succeeded = 0;
+        for i in 0..120 {
+            let step = gen_step(&mut rng, &model);
+            if run_step(&mut contract, &mut model, &step, i + 1).is_ok() {
+                succeeded += 1;
+            }
+        }
+        // the sequence must reach the states behind the registration
+        assert!(
+            succeeded > 10,
+            "seed {}: {} steps succeeded",
+            seed,
+            succeeded
+        );
+    }
+}
+
+#[test]
+fn state_machine_panic_test() {
+    let context = get_context("bob_near".to_string(), vec![], false);
+    testing_env!(context);
+    let mut contract = DID::new("alice_near".to_string(), None);
+    let mut model = Model::default();
+
+    let k0 = default_pk();
+    let k1 = vec![0u8, 1];
+    let k2 = vec![1u8, 2, 3];
+    let k3 = vec![2u8, 9];
+    let bob = "did:near:bob_near".to_string();
+    let carol = "did:near:carol_near".to_string();
+    let dave = "did:near:dave_near".to_string();
+    let https = "https://a.example.com".to_string();
+    let ftp = "ftp://example.com".to_string();
+    let context1 = "https://example.com/v1".to_string();
+    let by_bob = |op| step("bob_near", default_pk(), op);
+    let by_carol = |op| step("carol_near", vec![0u8, 1], op);
+    let by_dave = |op| step("dave_near", default_pk(), op);
+    let steps = vec![
+        (
+            by_bob(Op::AddKey(k1.clone(), bob.clone())),
+            Some("did doesn't exist"),
+        ),
+        (by_bob(Op::Reg), None),
+        (by_bob(Op::Reg), Some("reg_did_using_account, did exists")),
+        (
+            step("bob_near", k1.clone(), Op::AddController(carol.clone())),
+            Some("check_pk_access, pk doesn't exist"),
+        ),
+        (
+            by_bob(Op::AddController("did:near:".to_string())),
+            Some("check_did, invalid DID"),
+        ),
+        (by_bob(Op::AddController(carol.clone())), None),
+        (
+            by_bob(Op::AddController(carol.clone())),
+            Some("add_controller, controller exists"),
+        ),
+        (
+            by_bob(Op::RemoveController(dave.clone())),
+            Some("remove_controller, controller doesn't exist"),
+        ),
+        (by_bob(Op::AddKey(k1.clone(), bob.clone())), None),
+        (
+            by_bob(Op::AddKey(k1.clone(), bob.clone())),
+            Some("add_key, pk exists"),
+        ),
+        (
+            by_bob(Op::AddKey(vec![], bob.clone())),
+            Some("add_key, public key is empty"),
+        ),
+        (
+            step("bob_near", k1.clone(), Op::SetAuthKey(k2.clone())),
+            Some("check_pk_access, pk is not authentication"),
+        ),
+        (
+            by_bob(Op::AddNewAuthKey(k1.clone(), bob.clone())),
+            Some("add_new_auth_key, pk exists"),
+        ),
+        (
+            by_bob(Op::AddNewAuthKey(vec![], bob.clone())),
+            Some("add_new_auth_key, public key is empty"),
+        ),
+        (by_bob(Op::AddNewAuthKey(k2.clone(), bob.clone())), None),
+        (
+            by_bob(Op::SetAuthKey(k3.clone())),
+            Some("set_pk_auth, pk doesn't exist"),
+        ),
+        (
+            by_bob(Op::SetAuthKey(k2.clone())),
+            Some("set_pk_auth, pk is already auth key"),
+        ),
+        (
+            by_bob(Op::DeactivateAuthKey(k1.clone())),
+            Some("remove_pk_auth, pk is not auth key"),
+        ),
+        (
+            by_bob(Op::DeactivateAuthKey(k3.clone())),
+            Some("remove_pk_auth, pk doesn't exist"),
+        ),
+        (
+            by_bob(Op::DeactivateKey(k3.clone())),
+            Some("deactivate_pk, pk doesn't exist"),
+        ),
+        (by_bob(Op::DeactivateKey(k2.clone())), None),
+        (
+            by_bob(Op::DeactivateKey(k2.clone())),
+            Some("deactivate_pk, pk is deactivated"),
+        ),
+        (
+            by_bob(Op::SetAuthKey(k2.clone())),
+            Some("set_pk_auth, pk is deactivated"),
+        ),
+        (
+            by_bob(Op::DeactivateAuthKey(k2.clone())),
+            Some("remove_pk_auth, pk is deactivated"),
+        ),
+        (
+            step(
+                "bob_near",
+                k2.clone(),
+                Op::AddService("s1".to_string(), https.clone()),
+            ),
+            Some("check_pk_access, pk is deactivated"),
+        ),
+        (
+            by_bob(Op::AddService("bad id".to_string(), https.clone())),
+            Some("add_service, invalid character ' ' in \"bad id\""),
+        ),
+        (
+            by_bob(Op::AddService("s1".to_string(), ftp.clone())),
+            Some("add_service, scheme \"ftp\" is not allowed"),
+        ),
+        (
+            by_bob(Op::AddService("s1".to_string(), https.clone())),
+            None,
+        ),
+        (
+            by_bob(Op::AddService("s1".to_string(), https.clone())),
+            Some("add_service, service exists"),
+        ),
+        (
+            by_bob(Op::UpdateService("s2".to_string(), https.clone())),
+            Some("update_service, service doesn't exist"),
+        ),
+        (
+            by_bob(Op::UpdateService("s1".to_string(), ftp.clone())),
+            Some("update_service, scheme \"ftp\" is not allowed"),
+        ),
+        (
+            by_bob(Op::UpdateService("s1".to_string(), carol.clone())),
+            None,
+        ),
+        (
+            by_bob(Op::RemoveService("s2".to_string())),
+            Some("remove_service, service doesn't exist"),
+        ),
+        (
+            by_bob(Op::AddContext(vec!["example".to_string()])),
+            Some("add_context, \"example\" is not an absolute URI"),
+        ),
+        (
+            by_bob(Op::AddContext(vec![
+                context1.clone(),
+                DEFAULT_CONTEXT1.to_string(),
+            ])),
+            None,
+        ),
+        (
+            by_bob(Op::RemoveContext(vec![DEFAULT_CONTEXT1.to_string()])),
+            Some("remove_context, context doesn't exist"),
+        ),
+        (
+            by_bob(Op::SetContexts(vec![ftp.clone()])),
+            Some("set_contexts, scheme \"ftp\" is not allowed"),
+        ),
+        (
+            by_carol(Op::AddNewAuthKeyByController(
+                bob.clone(),
+                k3.clone(),
+                bob.clone(),
+            )),
+            Some("did doesn't exist"),
+        ),
+        (by_carol(Op::Reg), None),
+        (by_dave(Op::Reg), None),
+        (
+            by_dave(Op::SetAuthKeyByController(bob.clone(), k1.clone())),
+            Some("set_auth_key_by_controller, signer is not controller"),
+        ),
+        (
+            by_dave(Op::DeactivateAuthKeyByController(bob.clone(), k1.clone())),
+            Some("deactivate_auth_key_by_controller, signer is not controller"),
+        ),
+        (
+            by_dave(Op::AddNewAuthKeyByController(
+                bob.clone(),
+                k3.clone(),
+                bob.clone(),
+            )),
+            Some("add_new_auth_key_by_controller, signer is not controller"),
+        ),
+        (
+            by_carol(Op::AddNewAuthKeyByController(
+                bob.clone(),
+                k3.clone(),
+                "near:bob".to_string(),
+            )),
+            Some("check_did, invalid DID"),
+        ),
+        (
+            by_carol(Op::AddNewAuthKeyByController(
+                bob.clone(),
+                k1.clone(),
+                bob.clone(),
+            )),
+            Some("add_new_auth_key_by_controller, pk exists"),
+        ),
+        (
+            step(
+                "carol_near",
+                k0.clone(),
+                Op::AddNewAuthKeyByController(bob.clone(), k3.clone(), bob.clone()),
+            ),
+            Some("check_pk_access, pk doesn't exist"),
+        ),
+        (
+            by_carol(Op::AddNewAuthKeyByController(
+                bob.clone(),
+                k3.clone(),
+                bob.clone(),
+            )),
+            None,
+        ),
+        (
+            by_carol(Op::SetAuthKeyByController(bob.clone(), k1.clone())),
+            None,
+        ),
+        (
+            by_carol(Op::DeactivateAuthKeyByController(bob.clone(), k1.clone())),
+            None,
+        ),
+        (by_bob(Op::DeactivateDid), None),
+        (by_bob(Op::DeactivateDid), Some("did status is not valid")),
+        (
+            by_carol(Op::SetAuthKeyByController(bob.clone(), k1.clone())),
+            Some("did status is not valid"),
+        ),
+    ];
+    for (i, (step, expected)) in steps.iter().enumerate() {
+        let result = run_step(&mut contract, &mut model, step, i as u64 + 1);
+        assert_eq!(result.err().as_deref(), *expected, "{:?}", step);
+    }
+}
+
+#[test]
+fn boolean_query_test() {
+    let context = get_context("bob_near".to_string(), vec![], false);
+    testing_env!(context);
+    let mut contract = DID::new("alice_near".to_string(), None);
+    contract.reg_did_using_account();
+    let did = "did:near:bob_near".to_string();
+    let carol = "did:near:carol_near".to_string();
+    contract.add_key(vec![0, 1], did.clone());
+    contract.add_controller(carol.clone());
+
+    assert!(contract.is_authentication_key(did.clone(), default_pk()));
+    assert!(!contract.is_authentication_key(did.clone(), vec![0, 1]));
+    assert!(!contract.is_authentication_key(carol.clone(), default_pk()));
+
+    let key1 = "did:near:bob_near#keys-1".to_string();
+    let key2 = "did:near:bob_near#keys-2".to_string();
+    assert!(contract.has_relationship(did.clone(), key1.clone(), "authentication".to_string()));
+    assert!(contract.has_relationship(did.clone(), key2.clone(), "publicKey".to_string()));
+    assert!(!contract.has_relationship(did.clone(), key2.clone(), "authentication".to_string()));
+    assert!(!contract.has_relationship(did.clone(), key1.clone(), "assertionMethod".to_string()));
+    assert!(!contract.has_relationship(
+        did.clone(),
+        "did:near:bob_near#keys-3".to_string(),
+        "publicKey".to_string()
+    ));
+    assert!(!contract.has_relationship(carol.clone(), key1.clone(), "publicKey".to_string()));
+
+    // the controller DID is not registered
+    assert!(!contract.is_controller(did.clone(), carol.clone()));
+    let context = get_context_with_pk("carol_near".to_string(), vec![0, 2], vec![], false);
+    switch_context(context);
+    contract.reg_did_using_account();
+    assert!(contract.is_controller(did.clone(), carol.clone()));
+    assert!(!contract.is_controller(carol.clone(), did.clone()));
+
+    let context = get_context("bob_near".to_string(), vec![], false);
+    switch_context(context);
+    contract.deactivate_key(vec![0, 1]);
+    assert!(!contract.has_relationship(did.clone(), key2, "publicKey".to_string()));
+    contract.deactivate_did();
+    assert!(!contract.is_authentication_key(did.clone(), default_pk()));
+    assert!(!contract.is_controller(did.clone(), carol));
+    assert!(!contract.has_relationship(did, key1, "authentication".to_string()));
+}
+
+#[test]
+fn predecessor_auth_test() {
+    let mut context = get_context("bob_near".to_string(), vec![], false);
+    context.predecessor_account_id = "dao_near".to_string();
+    testing_env!(context.clone());
+    let mut contract = DID::new("alice_near".to_string(), None);
+    let dao = "did:near:dao_near".to_string();
+    let bob = "did:near:bob_near".to_string();
+
+    // a member of the DAO signs the transaction, the DAO contract calls the DID contract
+    contract.reg_did_using_predecessor();
+    assert_eq!(
+        contract.get_auth_mode(dao.clone()),
+        Some(AuthMode::Predecessor)
+    );
+    contract.add_service(
+        "dao".to_string(),
+        ServiceType::Type("LinkedDomains".to_string()),
+        ServiceEndpoint::Uri("https://dao.example.com".to_string()),
+        None,
+    );
+    contract.add_key(vec![0, 1], dao.clone());
+    contract.verify_signature();
+    let doc = contract.get_document(dao.clone()).unwrap();
+    assert!(doc.contains("did:near:dao_near#dao"));
+    assert!(doc.contains("\"authentication\":[]"));
+    let operations = contract.get_operations(dao.clone(), 0, 3);
+    assert_eq!(operations[1].signer_account, "bob_near");
+    assert_eq!(operations[1].predecessor_account, "dao_near");
+
+    // bob owns his DID by his key, the DAO controls it
+    context.predecessor_account_id = "bob_near".to_string();
+    switch_context(context.clone());
+    contract.reg_did_using_account();
+    assert_eq!(contract.get_auth_mode(bob.clone()), Some(AuthMode::Signer));
+    contract.add_controller(dao.clone());
+
+    context.signer_account_id = "carol_near".to_string();
+    context.signer_account_pk = vec![0, 9];
+    context.predecessor_account_id = "dao_near".to_string();
+    switch_context(context);
+    contract.verify_controller(bob.clone());
+    contract.add_new_auth_key_by_controller(bob.clone(), vec![0, 2], bob.clone());
+    assert!(contract.is_authentication_key(bob.clone(), vec![0, 2]));
+    assert_eq!(
+        contract.get_operations(bob.clone(), 2, 1)[0].controller,
+        Some(dao.clone())
+    );
+
+    contract.deactivate_did();
+    assert!(contract.get_document(dao).is_none());
+}
+
+#[test]
+#[should_panic(expected = "check_access, predecessor is not the DID account")]
+fn predecessor_auth_signer_test() {
+    let mut context = get_context("dao_near".to_string(), vec![], false);
+    context.predecessor_account_id = "dao_near".to_string();
+    testing_env!(context.clone());
+    let mut contract = DID::new("alice_near".to_string(), None);
+    contract.reg_did_using_predecessor();
+
+    // the DID owned by the contract can't be updated by a transaction signed by the contract account through another contract
+    context.predecessor_account_id = "carol_near".to_string();
+    switch_context(context);
+    contract.add_controller("did:near:carol_near".to_string());
+}
+
+#[test]
+fn predecessor_auth_key_test() {
+    let mut context = get_context("bob_near".to_string(), vec![], false);
+    context.predecessor_account_id = "dao_near".to_string();
+    testing_env!(context);
+    let mut contract = DID::new("alice_near".to_string(), None);
+    let dao = "did:near:dao_near".to_string();
+    contract.reg_did_using_predecessor();
+    contract.add_key(vec![0, 1], dao.clone());
+
+    // the keys of the DID owned by the contract can't authorize it, they aren't authentication keys
+    assert_panics_with(
+        || contract.add_new_auth_key(vec![0, 2], dao.clone()),
+        "add_new_auth_key, did is authorized by predecessor",
+    );
+    assert_panics_with(
+        || contract.set_auth_key(vec![0, 1]),
+        "set_auth_key, did is authorized by predecessor",
+    );
+    assert_panics_with(
+        || {
+            contract.patch_document(parse_patch(serde_json::json!([
+                { "op": "add", "path": "/authentication/-", "value": format!("{}#keys-1", dao) },
+            ])))
+        },
+        "patch_document, did is authorized by predecessor",
+    );
+    assert!(!contract.is_authentication_key(dao, vec![0, 1]));
+}
+
+#[test]
+fn network_did_test() {
+    let context = get_context("bob_near".to_string(), vec![], false);
+    testing_env!(context);
+    let mut config = Config::default();
+    config.network = Some("testnet".to_string());
+    let mut contract = DID::new("alice_near".to_string(), Some(config));
+    contract.reg_did_using_account();
+    let did = "did:near:testnet:bob_near".to_string();
+    contract.add_controller("did:near:testnet:carol_near".to_string());
+    contract.add_key(vec![0, 1], did.clone());
+
+    let doc = contract.get_document(did.clone()).unwrap();
+    assert!(doc.contains("\"id\":\"did:near:testnet:bob_near\""));
+    assert!(doc.contains("did:near:testnet:bob_near#keys-2"));
+    assert!(contract
+        .get_document("did:near:bob_near".to_string())
+        .is_none());
+    assert!(contract
+        .storage_balance_of("bob_near".to_string())
+        .is_some());
+    assert_eq!(contract.get_dids(0, 10), vec![did]);
+}
+
+#[test]
+#[should_panic(expected = "check_did, invalid DID")]
+fn network_controller_test() {
+    let context = get_context("bob_near".to_string(), vec![], false);
+    testing_env!(context);
+    let mut config = Config::default();
+    config.network = Some("testnet".to_string());
+    let mut contract = DID::new("alice_near".to_string(), Some(config));
+    contract.reg_did_using_account();
+    // the DID of another network
+    contract.add_controller("did:near:carol_near".to_string());
+}
+
+#[test]
+#[should_panic(expected = "new, invalid network")]
+fn invalid_network_test() {
+    let context = get_context("bob_near".to_string(), vec![], false);
+    testing_env!(context);
+    let mut config = Config::default();
+    config.method_prefix = "did:near:".to_string();
+    config.network = Some("test:net".to_string());
+    DID::new("alice_near".to_string(), Some(config));
+}
+
+fn test_keypair(seed: u8) -> ed25519_dalek::Keypair {
+    let secret = ed25519_dalek::SecretKey::from_bytes(&[seed; 32]).unwrap();
+    let public = ed25519_dalek::PublicKey::from(&secret);
+    ed25519_dalek::Keypair { secret, public }
+}
+
+/// the public key of NEAR format, the key type byte followed by the key.
+fn near_pk(keypair: &ed25519_dalek::Keypair) -> Vec<u8> {
+    let mut pk = vec![0u8];
+    pk.extend_from_slice(keypair.public.as_bytes());
+    pk
+}
+
+fn sign(keypair: &ed25519_dalek::Keypair, message: &str) -> Vec<u8> {
+    use ed25519_dalek::Signer;
+    keypair.sign(message.as_bytes()).to_bytes().to_vec()
+}
+
+#[test]
+fn key_did_test() {
+    let context = get_context("relayer_near".to_string(), vec![], false);
+    testing_env!(context);
+    let mut contract = DID::new("alice_near".to_string(), None);
+    let keypair = test_keypair(7);
+    let pk = near_pk(&keypair);
+    let implicit_account = key_did_id(&pk).unwrap();
+    let did = gen_did(&implicit_account);
+
+    // the relayer registers the DID for the user who has no account
+    let signature = sign(&keypair, &reg_did_message("alice_near", &did));
+    contract.reg_did_using_key(pk.clone(), signature);
+    assert!(contract.is_authentication_key(did.clone(), pk.clone()));
+    let doc = contract.get_document(did.clone()).unwrap();
+    assert!(doc.contains(&format!("{}#keys-1", did)));
+
+    // the implicit account manages the DID once it's funded
+    let context = get_context_with_pk(implicit_account.clone(), pk.clone(), vec![], false);
+    switch_context(context);
+    contract.add_context(vec!["https://example.com/v1".to_string()]);
+
+    // the DID is bound to bob, who manages it by his own key afterwards
+    let context = get_context("bob_near".to_string(), vec![], false);
+    switch_context(context);
+    let signature = sign(
+        &keypair,
+        &bind_account_message("alice_near", &did, "bob_near"),
+    );
+    contract.bind_account(did.clone(), pk.clone(), signature);
+    assert_eq!(
+        contract.get_bound_did("bob_near".to_string()),
+        Some(did.clone())
+    );
+    assert!(contract.is_authentication_key(did.clone(), default_pk()));
+    contract.add_controller("did:near:carol_near".to_string());
+    assert!(contract
+        .get_document(did.clone())
+        .unwrap()
+        .contains("did:near:carol_near"));
+    assert!(contract.get_document(gen_did("bob_near")).is_none());
+    assert!(contract.check_integrity(did.clone()).is_empty());
+
+    // the account can register its own DID after the bound DID is deactivated
+    contract.deactivate_did();
+    assert_eq!(contract.get_bound_did("bob_near".to_string()), None);
+    contract.reg_did_using_account();
+    assert!(contract.get_document(gen_did("bob_near")).is_some());
+}
+
+#[test]
+fn storage_bound_did_test() {
+    let context = get_context("relayer_near".to_string(), vec![], false);
+    testing_env!(context);
+    let mut contract = DID::new("alice_near".to_string(), None);
+    let keypair = test_keypair(7);
+    let pk = near_pk(&keypair);
+    let did = gen_did(&key_did_id(&pk).unwrap());
+    let signature = sign(&keypair, &reg_did_message("alice_near", &did));
+    contract.reg_did_using_key(pk.clone(), signature);
+
+    let mut context = get_context("bob_near".to_string(), vec![], false);
+    context.predecessor_account_id = "bob_near".to_string();
+    switch_context(context);
+    let signature = sign(
+        &keypair,
+        &bind_account_message("alice_near", &did, "bob_near"),
+    );
+    contract.bind_account(did.clone(), pk, signature);
+
+    // the storage of bob is the storage of the bound DID
+    let total = contract.storage_account.get(&did).unwrap().total;
+    let balance = contract.storage_deposit(None, None);
+    assert_eq!(balance.total.0, total + 10u128.pow(25));
+    assert_eq!(
+        contract
+            .storage_balance_of("bob_near".to_string())
+            .unwrap()
+            .total
+            .0,
+        balance.total.0
+    );
+    assert!(contract.storage_account.get(&gen_did("bob_near")).is_none());
+
+    let mut context = get_context("bob_near".to_string(), vec![], false);
+    context.predecessor_account_id = "bob_near".to_string();
+    context.attached_deposit = 1;
+    switch_context(context);
+    let balance = contract.storage_withdraw(None);
+    assert_eq!(balance.available.0, 0);
+}
+
+#[test]
+#[should_panic(expected = "reg_did_using_key, invalid signature")]
+fn key_did_signature_test() {
+    let context = get_context("relayer_near".to_string(), vec![], false);
+    testing_env!(context);
+    let mut contract = DID::new("alice_near".to_string(), None);
+    let keypair = test_keypair(7);
+    let pk = near_pk(&keypair);
+    let did = gen_did(&key_did_id(&pk).unwrap());
+    // the signature for another contract can't be replayed
+    let signature = sign(&keypair, &reg_did_message("other_near", &did));
+    contract.reg_did_using_key(pk, signature);
+}
+
+#[test]
+#[should_panic(expected = "bind_account, account has a did")]
+fn key_did_bind_test() {
+    let context = get_context("bob_near".to_string(), vec![], false);
+    testing_env!(context);
+    let mut contract = DID::new("alice_near".to_string(), None);
+    contract.reg_did_using_account();
+    let keypair = test_keypair(7);
+    let pk = near_pk(&keypair);
+    let did = gen_did(&key_did_id(&pk).unwrap());
+    let signature = sign(&keypair, &reg_did_message("alice_near", &did));
+    contract.reg_did_using_key(pk.clone(), signature);
+
+    let signature = sign(
+        &keypair,
+        &bind_account_message("alice_near", &did, "bob_near"),
+    );
+    contract.bind_account(did, pk, signature);
+}
+
+#[test]
+fn factory_test() {
+    let context = get_context("bob_near".to_string(), vec![], false);
+    testing_env!(context);
+    let mut contract = DID::new("alice_near".to_string(), None);
+    let pk = near_pk(&test_keypair(9));
+    let did = gen_did("user123.alice_near");
+
+    contract.create_account_and_did("user123".to_string(), pk.clone());
+    assert!(contract.is_authentication_key(did.clone(), pk.clone()));
+    // the storage balance covers the storage exactly, the rest funds the subaccount
+    let balance = contract
+        .storage_balance_of("user123.alice_near".to_string())
+        .unwrap();
+    assert!(balance.total.0 > 0);
+    assert_eq!(balance.available.0, 0);
+
+    switch_to_callback(vec![PromiseResult::Successful(vec![])]);
+    assert!(contract.on_account_created(
+        did.clone(),
+        "user123.alice_near".to_string(),
+        "carol_near".to_string(),
+        U64::from(0),
+        U128::from(1),
+        U128::from(1),
+    ));
+    assert!(contract.get_document(did.clone()).is_some());
+    assert!(contract.check_integrity(did.clone()).is_empty());
+}
+
+#[test]
+fn factory_rollback_test() {
+    let context = get_context("bob_near".to_string(), vec![], false);
+    testing_env!(context.clone());
+    let mut contract = DID::new("alice_near".to_string(), None);
+    contract.reg_did_using_account();
+    let pk = near_pk(&test_keypair(9));
+    let did = gen_did("user123.alice_near");
+
+    contract.create_account_and_did("user123".to_string(), pk.clone());
+    let storage_balance = contract.storage_account.get(&did).unwrap().total;
+    // DIDs registered before the callback keep their order
+    for account in ["dave_near", "erin_near"].iter() {
+        switch_context(get_context(account.to_string(), vec![], false));
+        contract.reg_did_using_account();
+    }
+    // a deposit made by someone else for the DID
+    let mut deposit_context = get_context("erin_near".to_string(), vec![], false);
+    deposit_context.attached_deposit = 1000;
+    switch_context(deposit_context);
+    contract.storage_deposit(Some("user123.alice_near".to_string()), None);
+
+    switch_to_callback(vec![PromiseResult::Failed]);
+    assert!(!contract.on_account_created(
+        did.clone(),
+        "user123.alice_near".to_string(),
+        "carol_near".to_string(),
+        U64::from(1),
+        U128::from(10u128.pow(25)),
+        U128::from(10u128.pow(25) - storage_balance),
+    ));
+    assert!(contract.get_document(did.clone()).is_none());
+    assert!(contract.get_document_at(did.clone(), 1).is_none());
+    assert!(contract.find_dids_by_key(pk.clone(), None).is_empty());
+    assert_eq!(
+        contract.get_dids(0, 10),
+        vec![
+            gen_did("bob_near"),
+            gen_did("dave_near"),
+            gen_did("erin_near")
+        ]
+    );
+    assert_eq!(contract.get_did_count(), 4);
+    // the deposit made by someone else is kept for the account
+    assert_eq!(
+        contract
+            .storage_balance_of("user123.alice_near".to_string())
+            .unwrap()
+            .total
+            .0,
+        1000
+    );
+
+    // the name can be used again
+    switch_context(context);
+    contract.create_account_and_did("user123".to_string(), pk.clone());
+    assert!(contract.is_authentication_key(did, pk));
+}
+
+#[test]
+fn factory_invalid_test() {
+    let context = get_context("bob_near".to_string(), vec![], false);
+    testing_env!(context);
+    let mut contract = DID::new("alice_near".to_string(), None);
+    let pk = near_pk(&test_keypair(9));
+    contract.create_account_and_did("user123".to_string(), pk.clone());
+
+    let cases: Vec<(&str, Vec<u8>, &str)> = vec![
+        ("user123", pk.clone(), "create_account_and_did, did exists"),
+        (
+            "User123",
+            pk.clone(),
+            "create_account_and_did, invalid account name",
+        ),
+        (
+            "user.123",
+            pk.clone(),
+            "create_account_and_did, invalid account name",
+        ),
+        (
+            "user-",
+            pk.clone(),
+            "create_account_and_did, invalid account name",
+        ),
+        (
+            "",
+            pk.clone(),
+            "create_account_and_did, invalid account name",
+        ),
+        (
+            "user456",
+            pk[1..].to_vec(),
+            "create_account_and_did, invalid public key",
+        ),
+    ];
+    for (name, pk, expected) in cases {
+        let storage = env::storage_usage();
+        assert_panics_with(
+            || contract.create_account_and_did(name.to_string(), pk.clone()),
+            expected,
+        );
+        assert_eq!(env::storage_usage(), storage);
+    }
+
+    // only the contract account can invoke the callback
+    assert_panics_with(
+        || {
+            contract.on_account_created(
+                gen_did("user123.alice_near"),
+                "user123.alice_near".to_string(),
+                "bob_near".to_string(),
+                U64::from(0),
+                U128::from(1),
+                U128::from(1),
+            )
+        },
+        "on_account_created, only the contract account can invoke this method",
+    );
+}
+
+/// the document registered by bob in the document tests, with one key of each kind, a controller, a context and a service.
+fn bob_document_json() -> serde_json::Value {
+    let did = gen_did("bob_near");
+    serde_json::json!({
+        "@contexts": ["https://example.com/v1"],
+        "id": did,
+        "publicKey": [
+            {
+                "id": format!("{}#keys-1", did),
+                "type": "Ed25519VerificationKey2018",
+                "controller": did,
+                "publicKeyBase58": default_pk().to_base58(),
+            },
+            {
+                "id": format!("{}#keys-2", did),
+                "type": "Ed25519VerificationKey2018",
+                "controller": "did:near:carol_near",
+                "publicKeyBase58": near_pk(&test_keypair(2)).to_base58(),
+            },
+        ],
+        "authentication": [
+            format!("{}#keys-1", did),
+            {
+                "id": format!("{}#keys-3", did),
+                "type": "Ed25519VerificationKey2018",
+                "controller": did,
+                "publicKeyBase58": near_pk(&test_keypair(3)).to_base58(),
+            },
+        ],
+        "controller": ["did:near:carol_near"],
+        "service": [
+            {
+                "id": format!("{}#hub", did),
+                "type": "IdentityHub",
+                "serviceEndpoint": "https://hub.example.com",
+            },
+        ],
+    })
+}
+
+fn parse_document(value: serde_json::Value) -> Document {
+    serde_json::from_value(value).unwrap()
+}
+
+#[test]
+fn reg_did_with_document_test() {
+    let context = get_context("bob_near".to_string(), vec![], false);
+    testing_env!(context);
+    let mut contract = DID::new("alice_near".to_string(), None);
+    let did = gen_did("bob_near");
+
+    let doc = contract.reg_did_with_document(parse_document(bob_document_json()));
+    assert_eq!(Some(doc.clone()), contract.get_document(did.clone()));
+    let value: serde_json::Value = serde_json::from_str(&doc).unwrap();
+    let mut expected = bob_document_json();
+    expected["@contexts"] = serde_json::json!([
+        "https://www.w3.org/ns/did/v1",
+        "https://www.near.org/did/v1",
+        "https://example.com/v1"
+    ]);
+    for field in [
+        "@contexts",
+        "id",
+        "publicKey",
+        "authentication",
+        "controller",
+        "service",
+    ]
+    .iter()
+    {
+        assert_eq!(value[field], expected[field], "{}", field);
+    }
+    assert_eq!(contract.get_version_id(did.clone()), 1);
+    assert!(contract.check_integrity(did.clone()).is_empty());
+    assert!(contract.is_controller(did.clone(), "did:near:carol_near".to_string()));
+    assert!(contract.is_authentication_key(did.clone(), near_pk(&test_keypair(3))));
+    assert!(!contract.is_authentication_key(did.clone(), near_pk(&test_keypair(2))));
+
+    // the DID is managed as any other one
+    contract.remove_service("hub".to_string());
+    assert!(contract
+        .get_document(did.clone())
+        .unwrap()
+        .contains("\"service\":[]"));
+}
+
+#[test]
+fn reg_did_with_document_invalid_test() {
+    let cases: Vec<(Box<dyn Fn(&mut serde_json::Value)>, &str)> = vec![
+        (
+            Box::new(|v: &mut serde_json::Value| {
+                v["id"] = serde_json::json!("did:near:carol_near")
+            }),
+            "document id is not the DID",
+        ),
+        (
+            Box::new(|v: &mut serde_json::Value| {
+                v["authentication"].as_array_mut().unwrap().remove(0);
+            }),
+            "signer pk is not authentication",
+        ),
+        (
+            Box::new(|v: &mut serde_json::Value| {
+                v["authentication"][0] = serde_json::json!("did:near:bob_near#keys-4")
+            }),
+            "authentication \"did:near:bob_near#keys-4\" doesn't exist",
+        ),
+        (
+            Box::new(|v: &mut serde_json::Value| {
+                v["publicKey"][1]["id"] = serde_json::json!("did:near:bob_near#keys-5")
+            }),
+            "key \"did:near:bob_near#keys-3\" doesn't follow the existing keys",
+        ),
+        (
+            Box::new(|v: &mut serde_json::Value| {
+                v["publicKey"][1]["id"] = serde_json::json!("did:near:bob_near#keys-1")
+            }),
+            "key \"did:near:bob_near#keys-1\" is duplicated",
+        ),
+        (
+            Box::new(|v: &mut serde_json::Value| {
+                v["publicKey"][1]["type"] = serde_json::json!("EcdsaSecp256k1VerificationKey2019")
+            }),
+            "invalid type of key",
+        ),
+        (
+            Box::new(|v: &mut serde_json::Value| {
+                v["publicKey"][1]["publicKeyBase58"] = serde_json::json!(default_pk().to_base58())
+            }),
+            "reg_did_with_document, pk exists",
+        ),
+        (
+            Box::new(|v: &mut serde_json::Value| {
+                v["controller"] = serde_json::json!(["did:near:carol_near", "did:near:carol_near"])
+            }),
+            "reg_did_with_document, controller exists",
+        ),
+        (
+            Box::new(|v: &mut serde_json::Value| {
+                v["controller"] = serde_json::json!(["did:web:carol.com"])
+            }),
+            "check_did, invalid DID",
+        ),
+        (
+            Box::new(|v: &mut serde_json::Value| {
+                v["service"][0]["serviceEndpoint"] = serde_json::json!("ftp://hub.example.com")
+            }),
+            "scheme \"ftp\" is not allowed",
+        ),
+        (
+            Box::new(|v: &mut serde_json::Value| {
+                let service = v["service"][0].clone();
+                v["service"].as_array_mut().unwrap().push(service);
+            }),
+            "reg_did_with_document, service exists",
+        ),
+    ];
+    for (edit, expected) in cases {
+        let context = get_context("bob_near".to_string(), vec![], false);
+        testing_env!(context);
+        let mut contract = DID::new("alice_near".to_string(), None);
+        let mut value = bob_document_json();
+        edit(&mut value);
+        assert_panics_with(
+            || contract.reg_did_with_document(parse_document(value.clone())),
+            expected,
+        );
+    }
+}
+
+fn parse_patch(value: serde_json::Value) -> Vec<JsonPatchOp> {
+    serde_json::from_value(value).unwrap()
+}
+
+#[test]
+fn patch_document_test() {
+    let context = get_context("bob_near".to_string(), vec![], false);
+    testing_env!(context);
+    let mut contract = DID::new("alice_near".to_string(), None);
+    contract.reg_did_using_account();
+    let did = gen_did("bob_near");
+    let pk = near_pk(&test_keypair(2));
+
+    contract.patch_document(parse_patch(serde_json::json!([
+        { "op": "test", "path": "/updated", "value": 0 },
+        { "op": "add", "path": "/@contexts/-", "value": "https://example.com/v1" },
+        { "op": "add", "path": "/controller/-", "value": "did:near:carol_near" },
+        { "op": "add", "path": "/publicKey/-", "value": {
+            "id": format!("{}#keys-2", did),
+            "type": "Ed25519VerificationKey2018",
+            "controller": did,
+            "publicKeyBase58": pk.to_base58(),
+        } },
+        { "op": "add", "path": "/authentication/0", "value": format!("{}#keys-2", did) },
+        { "op": "add", "path": "/service/-", "value": {
+            "id": "hub",
+            "type": "IdentityHub",
+            "serviceEndpoint": "https://hub.example.com",
+        } },
+    ])));
+    let doc: serde_json::Value =
+        serde_json::from_str(&contract.get_document(did.clone()).unwrap()).unwrap();
+    assert_eq!(contract.get_version_id(did.clone()), 2);
+    assert_eq!(doc["@contexts"][2], "https://example.com/v1");
+    assert_eq!(
+        doc["controller"],
+        serde_json::json!(["did:near:carol_near"])
+    );
+    assert_eq!(
+        doc["authentication"],
+        serde_json::json!([format!("{}#keys-2", did), format!("{}#keys-1", did)])
+    );
+    assert_eq!(doc["service"][0]["id"], format!("{}#hub", did));
+    assert!(contract.is_controller(did.clone(), "did:near:carol_near".to_string()));
+    assert!(contract.is_authentication_key(did.clone(), pk.clone()));
+    assert!(contract.check_integrity(did.clone()).is_empty());
+
+    // removing a key deactivates it, an unchanged service keeps its version
+    contract.patch_document(parse_patch(serde_json::json!([
+        { "op": "remove", "path": "/authentication/0" },
+        { "op": "remove", "path": "/publicKey/1" },
+        { "op": "add", "path": "/@contexts/-", "value": "https://example.com/v2" },
+        { "op": "replace", "path": "/controller", "value": [] },
+    ])));
+    let doc: serde_json::Value =
+        serde_json::from_str(&contract.get_document(did.clone()).unwrap()).unwrap();
+    assert_eq!(contract.get_version_id(did.clone()), 3);
+    // the deactivated key is still listed
+    assert_eq!(doc["publicKey"].as_array().unwrap().len(), 2);
+    assert_eq!(doc["authentication"].as_array().unwrap().len(), 1);
+    assert_eq!(doc["@contexts"].as_array().unwrap().len(), 4);
+    assert!(contract.find_dids_by_key(pk.clone(), None).is_empty());
+    assert!(!contract.is_controller(did.clone(), "did:near:carol_near".to_string()));
+    let previous: serde_json::Value =
+        serde_json::from_str(&contract.get_document_at(did.clone(), 2).unwrap()).unwrap();
+    assert_eq!(doc["service"], previous["service"]);
+    assert_eq!(
+        contract.record.get(&did).unwrap().services,
+        vec![("hub".to_string(), 2)]
+    );
+    assert!(contract.check_integrity(did.clone()).is_empty());
+
+    // the deactivated key can't be an authentication key again
+    assert_panics_with(
+        || {
+            contract.patch_document(parse_patch(serde_json::json!([
+                { "op": "add", "path": "/authentication/-", "value": format!("{}#keys-2", did) },
+            ])))
+        },
+        "is deactivated",
+    );
+}
+
+#[test]
+fn patch_document_deactivated_key_test() {
+    let context = get_context("bob_near".to_string(), vec![], false);
+    testing_env!(context);
+    let mut contract = DID::new("alice_near".to_string(), None);
+    contract.reg_did_using_account();
+    let did = gen_did("bob_near");
+    let pk = near_pk(&test_keypair(2));
+    contract.add_key(pk.clone(), did.clone());
+    contract.deactivate_key(pk);
+
+    // the deactivated key is rendered, the patch of another member keeps it
+    contract.patch_document(parse_patch(serde_json::json!([
+        { "op": "add", "path": "/controller/-", "value": "did:near:carol_near" },
+    ])));
+    let doc: serde_json::Value =
+        serde_json::from_str(&contract.get_document(did.clone()).unwrap()).unwrap();
+    assert_eq!(doc["publicKey"].as_array().unwrap().len(), 2);
+    assert!(contract.is_controller(did.clone(), "did:near:carol_near".to_string()));
+    assert!(contract
+        .public_key
+        .get(&key_id(&did, 1))
+        .unwrap()
+        .is_deactivated());
+
+    // removing the deactivated key again is a no-op for the key
+    contract.patch_document(parse_patch(serde_json::json!([
+        { "op": "remove", "path": "/publicKey/1" },
+    ])));
+    assert!(contract.check_integrity(did.clone()).is_empty());
+
+    assert_panics_with(
+        || {
+            contract.patch_document(parse_patch(serde_json::json!([
+                { "op": "replace", "path": "/publicKey/1/controller", "value": "did:near:carol_near" },
+            ])))
+        },
+        "can't be changed",
+    );
+}
+
+#[test]
+fn patch_document_hidden_key_test() {
+    let context = get_context("bob_near".to_string(), vec![], false);
+    testing_env!(context);
+    let mut contract = DID::new("alice_near".to_string(), None);
+    contract.reg_did_using_account();
+    let did = gen_did("bob_near");
+    let pk = near_pk(&test_keypair(2));
+    contract.add_new_auth_key(pk.clone(), did.clone());
+    contract.deactivate_auth_key(pk.clone());
+
+    // the key isn't in the document any more, the patch of a service keeps it active
+    contract.patch_document(parse_patch(serde_json::json!([
+        { "op": "add", "path": "/service/-", "value": {
+            "id": "hub",
+            "type": "IdentityHub",
+            "serviceEndpoint": "https://hub.example.com",
+        } },
+    ])));
+    assert!(!contract
+        .public_key
+        .get(&key_id(&did, 1))
+        .unwrap()
+        .is_deactivated());
+    assert_eq!(contract.find_dids_by_key(pk.clone(), None).len(), 1);
+    contract.set_auth_key(pk.clone());
+    assert!(contract.is_authentication_key(did.clone(), pk));
+}
+
+#[test]
+fn patch_document_invalid_test() {
+    let cases: Vec<(serde_json::Value, &str)> = vec![
+        (
+            serde_json::json!([{ "op": "replace", "path": "/id", "value": "did:near:carol_near" }]),
+            "patch_document, \"/id\" is computed",
+        ),
+        (
+            serde_json::json!([{ "op": "move", "from": "/updated", "path": "/created" }]),
+            "patch_document, \"/updated\" is computed",
+        ),
+        (
+            serde_json::json!([{ "op": "add", "path": "/alias", "value": "bob" }]),
+            "patch_document, \"/alias\" is not a member of the document",
+        ),
+        (
+            serde_json::json!([{ "op": "replace", "path": "", "value": {} }]),
+            "patch_document, the document can't be replaced",
+        ),
+        (
+            serde_json::json!([{ "op": "replace", "path": "/@contexts/0", "value": "https://example.com/v1" }]),
+            "patch_document, \"/@contexts/0\" is a default context",
+        ),
+        (
+            serde_json::json!([{ "op": "replace", "path": "/@contexts", "value": [] }]),
+            "patch_document, the default contexts can't be changed",
+        ),
+        (serde_json::json!([]), "patch_document, patch is empty"),
+        (
+            serde_json::json!([{ "op": "test", "path": "/id", "value": "did:near:bob_near" }]),
+            "patch_document, patch doesn't change the document",
+        ),
+        (
+            serde_json::json!([{ "op": "replace", "path": "/controller", "value": [] }]),
+            "patch_document, patch doesn't change the document",
+        ),
+        (
+            serde_json::json!([
+                { "op": "add", "path": "/controller/-", "value": "did:near:carol_near" },
+                { "op": "test", "path": "/updated", "value": 2 },
+            ]),
+            "patch_document, operation 1, test of \"/updated\" failed",
+        ),
+        (
+            serde_json::json!([{ "op": "remove", "path": "/service/0" }]),
+            "patch_document, operation 0, path \"/service/0\" doesn't exist",
+        ),
+        (
+            serde_json::json!([{ "op": "add", "path": "/controller/01", "value": "did:near:carol_near" }]),
+            "patch_document, operation 0, invalid array index",
+        ),
+        (
+            serde_json::json!([{ "op": "add", "path": "/publicKey/0/usage", "value": "signing" }]),
+            "patch_document, invalid document",
+        ),
+        (
+            serde_json::json!([{ "op": "replace", "path": "/publicKey/0/controller", "value": "did:near:carol_near" }]),
+            "patch_document, key \"did:near:bob_near#keys-1\" can't be changed",
+        ),
+        (
+            serde_json::json!([{ "op": "add", "path": "/service/-", "value": { "id": "hub", "type": "IdentityHub", "serviceEndpoint": "ftp://hub.example.com" } }]),
+            "scheme \"ftp\" is not allowed",
+        ),
+    ];
+    for (patch, expected) in cases {
+        let context = get_context("bob_near".to_string(), vec![], false);
+        testing_env!(context);
+        let mut contract = DID::new("alice_near".to_string(), None);
+        contract.reg_did_using_account();
+        assert_panics_with(
+            || contract.patch_document(parse_patch(patch.clone())),
+            expected,
+        );
+    }
+}
+
+#[test]
+#[should_panic(expected = "patch_document, services is paused")]
+fn patch_document_paused_test() {
+    let mut context = get_context("bob_near".to_string(), vec![], false);
+    context.predecessor_account_id = "alice_near".to_string();
+    testing_env!(context);
+    let mut contract = DID::new("alice_near".to_string(), None);
+    contract.reg_did_using_account();
+    contract.pause(Some(vec![PauseGroup::Services]));
+    contract.patch_document(parse_patch(serde_json::json!([
+        { "op": "add", "path": "/controller/-", "value": "did:near:carol_near" },
+        { "op": "remove", "path": "/service/0" },
+    ])));
+}
+
+#[test]
+fn also_known_as_test() {
+    let context = get_context("bob_near".to_string(), vec![], false);
+    testing_env!(context);
+    let mut contract = DID::new("alice_near".to_string(), None);
+    contract.reg_did_using_account();
+    let bob = gen_did("bob_near");
+    let carol = gen_did("carol_near");
+
+    contract.add_also_known_as("did:web:bob.example.com".to_string());
+    contract.add_also_known_as(carol.clone());
+    let doc: serde_json::Value =
+        serde_json::from_str(&contract.get_document(bob.clone()).unwrap()).unwrap();
+    assert_eq!(
+        doc["alsoKnownAs"],
+        serde_json::json!(["did:web:bob.example.com", carol])
+    );
+    // carol doesn't link back yet
+    let verified: Vec<bool> = contract
+        .get_also_known_as(bob.clone())
+        .iter()
+        .map(|x| x.verified)
+        .collect();
+    assert_eq!(verified, vec![false, false]);
+    assert!(contract
+        .resolve(bob.clone())
+        .did_document_metadata
+        .verified_also_known_as
+        .is_empty());
+
+    let context = get_context("carol_near".to_string(), vec![], false);
+    switch_context(context);
+    contract.reg_did_using_account();
+    contract.add_also_known_as(bob.clone());
+    let result = contract.resolve(bob.clone());
+    assert_eq!(
+        result.did_document_metadata.verified_also_known_as,
+        vec![carol.clone()]
+    );
+    let metadata = serde_json::to_value(&result.did_document_metadata).unwrap();
+    assert_eq!(metadata["verifiedAlsoKnownAs"], serde_json::json!([carol]));
+    assert!(metadata.get("equivalentId").is_none());
+    assert_eq!(result.did_document_metadata.canonical_id, Some(bob.clone()));
+    assert_eq!(result.did_document_metadata.version_id, 3);
+    assert!(!result.did_document_metadata.deactivated);
+    assert_eq!(
+        result.did_document.unwrap().also_known_as,
+        vec!["did:web:bob.example.com".to_string(), carol.clone()]
+    );
+    assert_eq!(
+        contract
+            .resolve(carol.clone())
+            .did_document_metadata
+            .verified_also_known_as,
+        vec![bob.clone()]
+    );
+
+    // the link isn't verified after carol removes it
+    contract.remove_also_known_as(bob.clone());
+    assert!(contract
+        .resolve(bob.clone())
+        .did_document_metadata
+        .verified_also_known_as
+        .is_empty());
+    assert!(contract
+        .get_document(carol.clone())
+        .unwrap()
+        .find("alsoKnownAs")
+        .is_none());
+    assert!(contract.check_integrity(bob).is_empty());
+}
+
+#[test]
+fn also_known_as_invalid_test() {
+    let context = get_context("bob_near".to_string(), vec![], false);
+    testing_env!(context);
+    let mut contract = DID::new("alice_near".to_string(), None);
+    contract.reg_did_using_account();
+    contract.add_also_known_as("did:web:bob.example.com".to_string());
+
+    let cases = vec![
+        ("did:web:bob.example.com", "add_also_known_as, uri exists"),
+        (
+            "did:near:bob_near",
+            "add_also_known_as, alsoKnownAs can't be the DID itself",
+        ),
+        ("bob.example.com", "is not an absolute URI"),
+        ("did:web:bob example", "invalid character"),
+    ];
+    for (uri, expected) in cases {
+        assert_panics_with(|| contract.add_also_known_as(uri.to_string()), expected);
+    }
+    assert_panics_with(
+        || contract.remove_also_known_as("did:web:carol.example.com".to_string()),
+        "remove_also_known_as, uri doesn't exist",
+    );
+}
+
+#[test]
+fn also_known_as_document_test() {
+    let context = get_context("bob_near".to_string(), vec![], false);
+    testing_env!(context);
+    let mut contract = DID::new("alice_near".to_string(), None);
+    let did = gen_did("bob_near");
+    let mut value = bob_document_json();
+    value["alsoKnownAs"] = serde_json::json!(["did:web:bob.example.com"]);
+    let doc = contract.reg_did_with_document(parse_document(value));
+    assert!(doc.contains("\"alsoKnownAs\":[\"did:web:bob.example.com\"]"));
+
+    contract.patch_document(parse_patch(serde_json::json!([
+        { "op": "add", "path": "/alsoKnownAs/-", "value": "did:ont:AN5g6gz9V8jnNjcqBhR1ZKwdoMNpWr9e6c" },
+        { "op": "remove", "path": "/alsoKnownAs/0" },
+    ])));
+    assert_eq!(
+        contract
+            .get_also_known_as(did.clone())
+            .into_iter()
+            .map(|x| x.id)
+            .collect::<Vec<String>>(),
+        vec!["did:ont:AN5g6gz9V8jnNjcqBhR1ZKwdoMNpWr9e6c".to_string()]
+    );
+
+    // the links are removed with the DID
+    contract.deactivate_did();
+    let result = contract.resolve(did.clone());
+    assert!(result.did_document.is_none());
+    assert!(result.did_document_metadata.deactivated);
+    assert!(contract.get_also_known_as(did.clone()).is_empty());
+    assert!(contract.check_integrity(did).is_empty());
+}
+
+#[test]
+fn resolve_test() {
+    let context = get_context("bob_near".to_string(), vec![], false);
+    testing_env!(context);
+    let mut contract = DID::new("alice_near".to_string(), None);
+    contract.reg_did_using_account();
+
+    let result = serde_json::to_value(contract.resolve(gen_did("bob_near"))).unwrap();
+    let doc: serde_json::Value =
+        serde_json::from_str(&contract.get_document(gen_did("bob_near")).unwrap()).unwrap();
+    assert_eq!(result["didDocument"], doc);
+    assert_eq!(
+        result["didDocumentMetadata"],
+        serde_json::json!({
+            "created": 0,
+            "updated": 0,
+            "versionId": 1,
+            "deactivated": false,
+            "canonicalId": gen_did("bob_near"),
+        })
+    );
+    assert_eq!(
+        result["didResolutionMetadata"],
+        serde_json::json!({ "contentType": "application/did+ld+json" })
+    );
+
+    let result = contract.resolve(gen_did("carol_near"));
+    assert!(result.did_document.is_none());
+    assert_eq!(
+        result.did_resolution_metadata.error,
+        Some("notFound".to_string())
+    );
+    let result = contract.resolve("did:web:bob.example.com".to_string());
+    assert_eq!(
+        result.did_resolution_metadata.error,
+        Some("invalidDid".to_string())
+    );
+}
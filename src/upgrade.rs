@@ -0,0 +1,221 @@
+//! upgrade
+//!
+//! The code of the contract is upgraded by the contract itself: an admin stages the new code, the owner or
+//! enough admins approve its hash, and after the timelock the staged code is deployed and `migrate` is invoked
+//! in the same batch, so a failed migration reverts the deployment.
+
+use super::*;
+
+/// the storage key of the staged code, it's stored outside of the contract state to keep the state small.
+const STAGED_CODE_KEY: &[u8] = b"STAGED_CODE";
+/// the gas attached to the `migrate` call which follows the deployment.
+pub const MIGRATE_GAS: u64 = 100_000_000_000_000;
+/// one day, in nanoseconds.
+const DEFAULT_UPGRADE_TIMELOCK: u64 = 86_400_000_000_000;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct UpgradeConfig {
+    /// the time between staging the code and deploying it, in nanoseconds.
+    pub timelock: u64,
+    /// the number of admin approvals required if the owner doesn't approve.
+    pub threshold: u32,
+}
+
+impl Default for UpgradeConfig {
+    fn default() -> Self {
+        UpgradeConfig {
+            timelock: DEFAULT_UPGRADE_TIMELOCK,
+            threshold: 2,
+        }
+    }
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct StagedCode {
+    /// the sha256 hash of the code.
+    pub code_hash: Vec<u8>,
+    pub staged_by: String,
+    pub staged_at: u64,
+    /// the time the code can be deployed, the timelock is taken when the code is staged so a later config change doesn't shorten it.
+    pub deployable_at: u64,
+    /// the accounts which approved the code.
+    pub approvals: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StagedCodeView {
+    /// the sha256 hash of the code, in base58.
+    #[serde(rename(serialize = "codeHash", deserialize = "codeHash"))]
+    pub code_hash: String,
+    #[serde(rename(serialize = "stagedBy", deserialize = "stagedBy"))]
+    pub staged_by: String,
+    #[serde(rename(serialize = "stagedAt", deserialize = "stagedAt"))]
+    pub staged_at: u64,
+    #[serde(rename(serialize = "deployableAt", deserialize = "deployableAt"))]
+    pub deployable_at: u64,
+    pub approvals: Vec<String>,
+}
+
+#[near_bindgen]
+impl DID {
+    /// stage_code
+    ///
+    /// this method will stage the new code of the contract, the input of the call is the wasm code, not JSON.
+    /// the code which is already staged is replaced and its approvals are dropped.
+    /// it panics if the timelock would end after the largest block timestamp, the code couldn't be deployed.
+    /// only the owner and the admins have the right to invoke this method.
+    ///
+    /// log information: EVENT_JSON:{"standard":"did-near","version":"1.0.0","event":"stage_code","data":[{"code_hash":"...","staged_by":"alice.testnet"}]}
+    ///
+    pub fn stage_code(&mut self) {
+        self.check_admin();
+        let code = match env::input() {
+            Some(v) if !v.is_empty() => v,
+            _ => env::panic(b"stage_code, code is empty"),
+        };
+        let deployable_at = match env::block_timestamp().checked_add(self.upgrade.timelock) {
+            Some(v) => v,
+            None => env::panic(b"stage_code, timelock is too long"),
+        };
+        let staged_code = StagedCode {
+            code_hash: env::sha256(&code),
+            staged_by: env::predecessor_account_id(),
+            staged_at: env::block_timestamp(),
+            deployable_at,
+            approvals: vec![],
+        };
+        env::storage_write(STAGED_CODE_KEY, &code);
+        emit_event(
+            "stage_code",
+            serde_json::json!({
+                "code_hash": staged_code.code_hash.to_base58(),
+                "staged_by": staged_code.staged_by,
+            }),
+        );
+        self.staged_code = Some(staged_code);
+    }
+
+    /// approve_code
+    ///
+    /// this method will approve the staged code. `code_hash` is the base58 sha256 hash of the code,
+    /// it must be the hash of the staged code, so the approval can't be used for another code.
+    /// only the owner and the admins have the right to invoke this method.
+    ///
+    /// log information: EVENT_JSON:{"standard":"did-near","version":"1.0.0","event":"approve_code","data":[{"code_hash":"...","account_id":"alice.testnet"}]}
+    ///
+    pub fn approve_code(&mut self, code_hash: String) {
+        self.check_admin();
+        let account_id = env::predecessor_account_id();
+        let staged_code = match self.staged_code.as_mut() {
+            Some(v) => v,
+            None => env::panic(b"approve_code, no code is staged"),
+        };
+        if staged_code.code_hash.to_base58() != code_hash {
+            env::panic(b"approve_code, code hash doesn't match")
+        }
+        if staged_code.approvals.contains(&account_id) {
+            env::panic(b"approve_code, already approved")
+        }
+        staged_code.approvals.push(account_id.clone());
+        emit_event(
+            "approve_code",
+            serde_json::json!({ "code_hash": code_hash, "account_id": account_id }),
+        );
+    }
+
+    /// cancel_staged_code
+    ///
+    /// this method will remove the staged code. only the owner and the admins have the right to invoke this method.
+    ///
+    /// log information: EVENT_JSON:{"standard":"did-near","version":"1.0.0","event":"cancel_staged_code","data":[{"code_hash":"..."}]}
+    ///
+    pub fn cancel_staged_code(&mut self) {
+        self.check_admin();
+        let staged_code = match self.staged_code.take() {
+            Some(v) => v,
+            None => env::panic(b"cancel_staged_code, no code is staged"),
+        };
+        env::storage_remove(STAGED_CODE_KEY);
+        emit_event(
+            "cancel_staged_code",
+            serde_json::json!({ "code_hash": staged_code.code_hash.to_base58() }),
+        );
+    }
+
+    /// deploy_staged_code
+    ///
+    /// this method will deploy the staged code to the contract account and invoke `migrate` after it.
+    /// the code must be approved by the owner or by `threshold` admins, and the timelock it was staged with must have passed.
+    /// only the owner and the admins have the right to invoke this method.
+    ///
+    /// log information: EVENT_JSON:{"standard":"did-near","version":"1.0.0","event":"deploy_code","data":[{"code_hash":"..."}]}
+    ///
+    pub fn deploy_staged_code(&mut self) {
+        self.check_admin();
+        let staged_code = match self.staged_code.take() {
+            Some(v) => v,
+            None => env::panic(b"deploy_staged_code, no code is staged"),
+        };
+        if env::block_timestamp() < staged_code.deployable_at {
+            env::panic(b"deploy_staged_code, timelock has not passed")
+        }
+        let owner_approved = staged_code.approvals.contains(&self.roles.owner_id);
+        let admin_approvals = staged_code
+            .approvals
+            .iter()
+            .filter(|x| self.roles.admins.contains(*x))
+            .count();
+        if !owner_approved && admin_approvals < self.upgrade.threshold as usize {
+            env::panic(b"deploy_staged_code, code is not approved")
+        }
+        let code = env::storage_read(STAGED_CODE_KEY).unwrap();
+        env::storage_remove(STAGED_CODE_KEY);
+
+        emit_event(
+            "deploy_code",
+            serde_json::json!({ "code_hash": staged_code.code_hash.to_base58() }),
+        );
+        // both actions are in one receipt, if `migrate` fails the old code is kept
+        Promise::new(env::current_account_id())
+            .deploy_contract(code)
+            .function_call(b"migrate".to_vec(), b"{}".to_vec(), 0, MIGRATE_GAS);
+    }
+
+    /// set_upgrade_config
+    ///
+    /// this method will set the timelock and the admin approval threshold of upgrades. only the owner has the right to invoke this method.
+    /// the new timelock applies to the code staged after it, the staged code keeps the time it can be deployed.
+    ///
+    /// log information: EVENT_JSON:{"standard":"did-near","version":"1.0.0","event":"set_upgrade_config","data":[{"timelock":86400000000000,"threshold":2}]}
+    ///
+    pub fn set_upgrade_config(&mut self, upgrade: UpgradeConfig) {
+        self.check_owner();
+        if upgrade.threshold == 0 {
+            env::panic(b"set_upgrade_config, threshold must be positive")
+        }
+        emit_event("set_upgrade_config", serde_json::json!(upgrade));
+        self.upgrade = upgrade;
+    }
+
+    /// get_upgrade_config
+    ///
+    /// this method query the timelock and the admin approval threshold of upgrades.
+    ///
+    pub fn get_upgrade_config(&self) -> UpgradeConfig {
+        self.upgrade.clone()
+    }
+
+    /// get_staged_code
+    ///
+    /// this method query the hash, the approvals and the time of the staged code, so that it can be verified before it's deployed.
+    ///
+    pub fn get_staged_code(&self) -> Option<StagedCodeView> {
+        self.staged_code.as_ref().map(|x| StagedCodeView {
+            code_hash: x.code_hash.to_base58(),
+            staged_by: x.staged_by.clone(),
+            staged_at: x.staged_at,
+            deployable_at: x.deployable_at,
+            approvals: x.approvals.clone(),
+        })
+    }
+}
@@ -0,0 +1,243 @@
+use super::*;
+
+fn is_unreserved(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '-' || c == '.' || c == '_' || c == '~'
+}
+
+fn is_sub_delim(c: char) -> bool {
+    "!$&'()*+,;=".contains(c)
+}
+
+fn is_gen_delim(c: char) -> bool {
+    ":/?#[]@".contains(c)
+}
+
+/// check every "%" is followed by two hex digits and every other char passes `allowed`
+fn check_chars(s: &str, allowed: fn(char) -> bool) -> Result<(), String> {
+    let bytes = s.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i] as char;
+        if c == '%' {
+            if i + 2 >= bytes.len()
+                || !(bytes[i + 1] as char).is_ascii_hexdigit()
+                || !(bytes[i + 2] as char).is_ascii_hexdigit()
+            {
+                return Err(format!("invalid percent-encoding in \"{}\"", s));
+            }
+            i += 3;
+            continue;
+        }
+        if !c.is_ascii() || !allowed(c) {
+            return Err(format!("invalid character {:?} in \"{}\"", c, s));
+        }
+        i += 1;
+    }
+    Ok(())
+}
+
+/// check a service id is a valid DID URL fragment, RFC 3986 section 3.5, without the leading "#".
+pub fn check_service_id(id: &str) -> Result<(), String> {
+    if id.is_empty() {
+        return Err("service id is empty".to_string());
+    }
+    check_chars(id, |c| {
+        is_unreserved(c) || is_sub_delim(c) || ":@/?".contains(c)
+    })
+}
+
+/// check a service type is not empty and, if the registry is not empty, is registered.
+pub fn check_service_type(tp: &ServiceType, registry: &[String]) -> Result<(), String> {
+    let types = match tp {
+        ServiceType::Type(t) => vec![t],
+        ServiceType::Types(ts) => ts.iter().collect(),
+    };
+    if types.is_empty() {
+        return Err("service type is empty".to_string());
+    }
+    for t in types {
+        if t.is_empty() || t.chars().any(|c| c.is_whitespace()) {
+            return Err(format!("invalid service type \"{}\"", t));
+        }
+        if !registry.is_empty() && !registry.contains(t) {
+            return Err(format!("service type \"{}\" is not registered", t));
+        }
+    }
+    Ok(())
+}
+
+/// check a URI is an absolute URI, RFC 3986 section 4.3, whose scheme is in `schemes`.
+pub fn check_uri(uri: &str, schemes: &[String]) -> Result<(), String> {
+    let colon = uri
+        .find(':')
+        .ok_or_else(|| format!("\"{}\" is not an absolute URI", uri))?;
+    let scheme = &uri[..colon];
+    let mut scheme_chars = scheme.chars();
+    match scheme_chars.next() {
+        Some(c) if c.is_ascii_alphabetic() => (),
+        _ => return Err(format!("invalid scheme in \"{}\"", uri)),
+    }
+    if !scheme_chars.all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c)) {
+        return Err(format!("invalid scheme in \"{}\"", uri));
+    }
+    let scheme = scheme.to_ascii_lowercase();
+    if !schemes.contains(&scheme) {
+        return Err(format!("scheme \"{}\" is not allowed", scheme));
+    }
+
+    let rest = &uri[colon + 1..];
+    check_chars(rest, |c| {
+        is_unreserved(c) || is_sub_delim(c) || is_gen_delim(c)
+    })?;
+    if rest.starts_with("//") {
+        let authority = rest[2..].split(|c| c == '/' || c == '?' || c == '#').next();
+        let host = authority.unwrap_or("").rsplit('@').next().unwrap_or("");
+        if host.is_empty() {
+            return Err(format!("\"{}\" has no host", uri));
+        }
+    } else if rest.is_empty() {
+        return Err(format!("\"{}\" is not an absolute URI", uri));
+    }
+    Ok(())
+}
+
+/// check an alsoKnownAs entry is an absolute URI of any scheme, such as "did:web:example.com" or "https://example.com".
+pub fn check_also_known_as(uri: &str) -> Result<(), String> {
+    let scheme = uri.split(':').next().unwrap_or("").to_ascii_lowercase();
+    check_uri(uri, &[scheme])
+}
+
+/// check a JSON-LD context is an absolute http(s) URI and, if the allowlist is not empty, is allowed.
+pub fn check_context(context: &str, allowlist: &[String]) -> Result<(), String> {
+    check_uri(context, &["https".to_string(), "http".to_string()])?;
+    if !allowlist.is_empty() && !allowlist.iter().any(|x| x == context) {
+        return Err(format!("context \"{}\" is not allowed", context));
+    }
+    Ok(())
+}
+
+/// check the method prefix is "did:" followed by the method name and ":", and the network segment, if any, is made of the chars of a method-specific id but ":".
+pub fn check_did_prefix(method_prefix: &str, network: &Option<String>) -> Result<(), String> {
+    let method = method_prefix
+        .strip_prefix("did:")
+        .and_then(|x| x.strip_suffix(':'))
+        .unwrap_or("");
+    if method.is_empty()
+        || !method
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit())
+    {
+        return Err(format!("invalid method prefix \"{}\"", method_prefix));
+    }
+    if let Some(network) = network {
+        if network.is_empty()
+            || !network
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_')
+        {
+            return Err(format!("invalid network \"{}\"", network));
+        }
+    }
+    Ok(())
+}
+
+/// check the name is one part of a NEAR account id: lowercase letters and digits, separated by single "-" or "_".
+pub fn check_account_name(name: &str) -> Result<(), String> {
+    let mut previous_separator = true;
+    for c in name.chars() {
+        let separator = c == '-' || c == '_';
+        if !(separator || c.is_ascii_lowercase() || c.is_ascii_digit())
+            || (separator && previous_separator)
+        {
+            return Err(format!("invalid account name \"{}\"", name));
+        }
+        previous_separator = separator;
+    }
+    if previous_separator {
+        return Err(format!("invalid account name \"{}\"", name));
+    }
+    Ok(())
+}
+
+/// check a service endpoint, a URI, a map or an ordered set of URIs and maps.
+///
+/// the "uri" entry of a map is checked as a URI.
+pub fn check_service_endpoint(
+    endpoint: &ServiceEndpoint,
+    schemes: &[String],
+) -> Result<(), String> {
+    match endpoint {
+        ServiceEndpoint::Uri(uri) => check_uri(uri, schemes),
+        ServiceEndpoint::Map(map) => check_endpoint_map(map, schemes),
+        ServiceEndpoint::Set(set) => {
+            if set.is_empty() {
+                return Err("service endpoint set is empty".to_string());
+            }
+            for v in set.iter() {
+                match v {
+                    ServiceEndpoint::Uri(uri) => check_uri(uri, schemes)?,
+                    ServiceEndpoint::Map(map) => check_endpoint_map(map, schemes)?,
+                    ServiceEndpoint::Set(_) => {
+                        return Err("service endpoint set can't contain a set".to_string())
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+/// check the id, type, endpoint and extension properties of a service.
+pub fn check_service(service: &Service, config: &Config) -> Result<(), String> {
+    check_service_id(&service.id)?;
+    check_service_type(&service.tp, &config.service_types)?;
+    check_service_endpoint(&service.service_endpoint, &config.endpoint_schemes)?;
+    if service.properties.contains_reserved_key() {
+        return Err("reserved service property".to_string());
+    }
+    Ok(())
+}
+
+/// the length of the service serialized as JSON, in bytes.
+pub fn service_json_length(service: &Service) -> usize {
+    serde_json::to_vec(service).map(|x| x.len()).unwrap_or(0)
+}
+
+/// check the number of items doesn't exceed the limit.
+pub fn check_count(name: &str, count: usize, max: u32) -> Result<(), String> {
+    if count > max as usize {
+        return Err(format!("too many {}, the limit is {}", name, max));
+    }
+    Ok(())
+}
+
+/// check a field is not empty.
+pub fn check_not_empty(name: &str, length: usize) -> Result<(), String> {
+    if length == 0 {
+        return Err(format!("{} is empty", name));
+    }
+    Ok(())
+}
+
+/// check the length of a field doesn't exceed the limit.
+pub fn check_length(name: &str, length: usize, max: u32) -> Result<(), String> {
+    if length > max as usize {
+        return Err(format!("{} is too long, the limit is {} bytes", name, max));
+    }
+    Ok(())
+}
+
+/// panic with the error message prefixed by the method name if the result is an error.
+pub fn assert_valid(method: &str, result: Result<(), String>) {
+    if let Err(e) = result {
+        env::panic(format!("{}, {}", method, e).as_bytes())
+    }
+}
+
+fn check_endpoint_map(map: &JsonMap, schemes: &[String]) -> Result<(), String> {
+    match map.get("uri") {
+        Some(serde_json::Value::String(uri)) => check_uri(&uri, schemes),
+        Some(_) => Err("\"uri\" of service endpoint map is not a string".to_string()),
+        None => Ok(()),
+    }
+}